@@ -1,43 +1,99 @@
 use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    ffi::OsStr,
     fs,
-    io,
+    io::{self, IsTerminal, Read, Write},
     path::{Path, PathBuf},
+    sync::Arc,
+    time::{Instant, SystemTime},
 };
 
-use color_eyre::eyre::{bail, eyre, Result};
-use derive_config::DeriveTomlConfig;
-use rand::{rng, seq::IndexedRandom, Rng};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, NaiveTime, Timelike, Utc, Weekday};
+use color_eyre::eyre::{bail, eyre, Report, Result};
+use derive_config::{DeriveJsonConfig, DeriveTomlConfig};
+use futures::{stream, StreamExt};
+use rand::{rngs::StdRng, seq::IndexedRandom, Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
 use serenity::{
     all::{
         ActivityData,
-        ActivityType,
+        Attachment,
+        ButtonStyle,
+        Channel,
         ChannelId,
+        ChannelType,
         Command,
+        CommandDataOption,
+        CommandDataOptionValue,
         CommandInteraction,
+        CommandOptionType,
+        ComponentInteraction,
+        CreateActionRow,
+        CreateAllowedMentions,
         CreateAttachment,
+        CreateAutocompleteResponse,
+        CreateButton,
         CreateCommand,
+        CreateCommandOption,
+        CreateEmbed,
+        CreateEmbedAuthor,
+        CreateEmbedFooter,
+        CreateForumPost,
         CreateInteractionResponse,
         CreateInteractionResponseMessage,
+        CreateMessage,
+        CreatePoll,
+        CreatePollAnswer,
+        CurrentUser,
         EditGuild,
+        EditInteractionResponse,
+        EditMember,
+        EditMessage,
+        Guild,
         GuildId,
+        GuildMemberUpdateEvent,
+        ImageHash,
         Interaction,
+        Member,
+        Message,
+        MessageId,
         OnlineStatus,
+        PartialGuild,
         PermissionOverwrite,
         PermissionOverwriteType,
+        ReactionType,
         Ready,
+        Role,
         RoleId,
+        Timestamp,
+        UserId,
         VoiceState,
     },
     async_trait,
+    http::{Http, StatusCode},
     model::Permissions,
     prelude::*,
     Client,
 };
-use tokio::time::{sleep, Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::Notify,
+    time::{sleep, timeout, Duration},
+};
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::{
+    fmt,
+    layer::SubscriberExt,
+    reload,
+    util::SubscriberInitExt,
+    EnvFilter,
+    Registry,
+};
 
-#[derive(Clone, Default, DeriveTomlConfig, Deserialize, Serialize)]
+#[derive(Clone, DeriveTomlConfig, Deserialize, Serialize)]
 #[serde(default)] /* Default new fields instead of overwriting */
+#[allow(clippy::struct_excessive_bools)] /* Independent config toggles */
 struct Config {
     /// Discord token
     token: String,
@@ -51,528 +107,9017 @@ struct Config {
     /// The public video channel ID, which users are given access to when joining the voice channel.
     video: ChannelId,
 
+    /// Additional voice and video channel pairs, handled like `voice` and `video`.
+    voice_pairs: Vec<VoicePair>,
+
+    /// Partner servers every applied icon is shared with, announced in their channel and
+    /// optionally applied there too (empty keeps icon changes to this guild).
+    broadcast_targets: Vec<BroadcastTarget>,
+
+    /// Presence shown while members are in a video channel, `{count}` is replaced by how many
+    /// (unset always shows the idle texts).
+    presence_active_template: Option<String>,
+
+    /// Presence texts rotated through while the video channels are empty.
+    presence_idle_texts: Vec<String>,
+
+    /// How often (minutes) the idle presence moves on to the next text.
+    presence_rotate_minutes: u64,
+
     /// The alerts role ID that users can add/remove with the /alerts command.
     alerts: RoleId,
 
+    /// Whether successful /alerts confirmations are only visible to the invoking user (errors always are).
+    alerts_ephemeral: bool,
+
+    /// How many times the member fetch and role change of /alerts are tried before giving up.
+    alerts_retry_attempts: u32,
+
+    /// Named presets of role IDs that /alerts adds/removes together (e.g. `all = [1, 2, 3]`).
+    alert_presets: BTreeMap<String, Vec<RoleId>>,
+
     /// Path to a directory of images that will be used when randomizing the server icon.
     server_icons_unused: PathBuf,
 
     /// Path to a directory of images that have already been used as server icons.
     server_icons_used: PathBuf,
 
+    /// Only select icons modified within this many days (unset allows every icon).
+    icon_max_age_days: Option<u64>,
+
+    /// URL of an iCalendar feed; while an event is active, the icon whose filename matches its
+    /// title is applied instead of the normal rotation.
+    icon_calendar_url: Option<String>,
+
+    /// How often (minutes) the icon calendar is fetched.
+    icon_calendar_refresh_minutes: u64,
+
+    /// Feature flag endpoint polled for the active icon theme, answering with a JSON string or
+    /// an object holding it under `icon_flag_field`; it wins over `weekday_themes` and null or
+    /// an empty string clears it.
+    icon_flag_url: Option<String>,
+
+    /// The field of a flag response object that holds the theme.
+    icon_flag_field: String,
+
+    /// How often (minutes) the flag endpoint is polled.
+    icon_flag_refresh_minutes: u64,
+
+    /// Theme used while the flag endpoint can't be read (unset falls back to the normal
+    /// rotation).
+    icon_flag_default: Option<String>,
+
+    /// How long (seconds) downloads such as the icon calendar and imported icons may take.
+    http_timeout_seconds: u64,
+
+    /// Directory used when the unused pool is empty and nothing can be recycled; its icons are
+    /// never moved.
+    server_icons_fallback: Option<PathBuf>,
+
+    /// Include symlinked icons, which are moved by relinking them to their resolved target so a
+    /// shared pool is left untouched; when disabled they are skipped with a log message.
+    follow_symlinks: bool,
+
+    /// Directory `/icon backup` saves the current guild icon to, kept outside the rotation.
+    icon_backup_dir: Option<PathBuf>,
+
+    /// Directory a copy of every applied icon is archived to (unset disables archiving).
+    icon_archive_dir: Option<PathBuf>,
+
+    /// Directory icons past their expiry date are moved to from the unused directory (unset
+    /// leaves them in place, skipped by the rotation).
+    expired_icons_dir: Option<PathBuf>,
+
+    /// Archive filename template; `{date}` (YYYY-MM-DD), `{time}` (HHMMSS), `{epoch}`, `{name}`
+    /// (the filename), `{stem}` and `{ext}` are replaced, all in UTC.
+    icon_archive_template: String,
+
+    /// Git repository cloned into `server_icons_unused` and pulled periodically; its files are
+    /// never moved so pulls stay clean.
+    icon_git_repo: Option<String>,
+
+    /// How often (minutes) the icon repository is pulled.
+    icon_git_refresh_minutes: u64,
+
+    /// Move applied icons to the used directory; when disabled the directories are never touched
+    /// (so they can be read-only), nothing is recycled and only the current icon is skipped.
+    move_applied_icons: bool,
+
+    /// Stage each scheduled icon in `approval_channel` with Approve/Reject buttons instead of
+    /// applying it directly.
+    icon_requires_approval: bool,
+
+    /// The staff channel ID that icons awaiting approval are posted to (defaults to `log_channel`).
+    approval_channel: Option<ChannelId>,
+
+    /// How long (minutes) a staged icon waits before it is applied anyway (0 waits forever).
+    approval_timeout_minutes: u64,
+
+    /// Avoid picking any of the last N applied icons again while the pool has others left, the
+    /// current icon included; they are kept in the state file, so this holds across restarts and
+    /// right after used icons are recycled (0 allows immediate repeats).
+    avoid_last_n: usize,
+
+    /// Skip animated icons while the server lacks the `ANIMATED_ICON` feature, e.g. after losing
+    /// boosts, instead of having Discord reject them.
+    skip_animated_without_feature: bool,
+
+    /// How many candidates a rotation tries when Discord rejects the uploaded image.
+    icon_upload_attempts: u32,
+
+    /// Lower the weight of icons shown within this many days, favouring the least recently shown
+    /// across restarts (unset disables it).
+    recency_weight_days: Option<u64>,
+
+    /// How many of the oldest used icons return to the pool when it empties (unset returns all).
+    recycle_batch_size: Option<usize>,
+
+    /// Restricts the rotation on a day of the week (UTC) to icons whose sidecar `theme` matches,
+    /// e.g. `saturday = "weekend"`; days without a mapping or matching icons use the whole pool.
+    weekday_themes: BTreeMap<String, String>,
+
+    /// Named theme packs that `/theme apply` switches the icon, banner and name to together.
+    themes: BTreeMap<String, ThemePack>,
+
+    /// Icons applied when the member count first reaches a milestone.
+    member_milestones: Vec<MemberMilestone>,
+
+    /// How long (hours) a milestone icon stays before the rotation resumes.
+    milestone_icon_hours: u64,
+
+    /// How long (hours) an `/icon poll` stays open, at most a week.
+    icon_poll_hours: u64,
+
+    /// Convert AVIF and WebP icons to PNG/GIF before uploading (requires the `convert` build feature).
+    convert_icons: bool,
+
+    /// Attach a PNG thumbnail of at most this many pixels to announcements instead of the full
+    /// icon; animated icons show their first frame (requires the `convert` build feature).
+    log_thumbnail_size: Option<u32>,
+
+    /// What happens to the server icon when the bot starts, see [`StartupIconBehavior`].
+    startup_icon_behavior: StartupIconBehavior,
+
     /// Minimum randomized delay (hours) before applying a new server icon.
     server_icons_delay_min_hours: u64,
 
     /// Maximum randomized delay (hours) before applying a new server icon.
     server_icons_delay_max_hours: u64,
-}
 
-impl TypeMapKey for Config {
-    type Value = Self;
-}
+    /// Minimum randomized delay as a duration like `30m` or `1h30m`, preferred over
+    /// `server_icons_delay_min_hours` when set.
+    server_icons_delay_min: Option<String>,
 
-fn is_supported_icon(path: &Path) -> bool {
-    let Some(extension) = path.extension().and_then(|ext| ext.to_str()) else {
-        return false;
-    };
+    /// Maximum randomized delay as a duration like `2h` or `1d`, preferred over
+    /// `server_icons_delay_max_hours` when set.
+    server_icons_delay_max: Option<String>,
 
-    matches!(
-        extension.to_ascii_lowercase().as_str(),
-        "png" | "jpg" | "jpeg" | "gif" | "webp"
-    )
-}
+    /// Maximum number of applied icons kept in the history saved with the state.
+    history_max_entries: usize,
 
-fn load_icon_paths(directory: &Path) -> Result<Vec<PathBuf>> {
-    if directory.as_os_str().is_empty() {
-        return Ok(Vec::new());
-    }
+    /// Number of days applied icons are kept in the history (0 keeps them until the cap is hit).
+    history_retention_days: u64,
 
-    let metadata = match fs::metadata(directory) {
-        Ok(meta) => meta,
-        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
-            fs::create_dir_all(directory)?;
-            return Ok(Vec::new());
-        }
-        Err(error) => bail!(
-            "Failed to access server icon path '{}': {error}",
-            directory.display()
-        ),
-    };
+    /// The channel ID a digest of the applied icons is posted to (unset disables it).
+    summary_channel: Option<ChannelId>,
 
-    if !metadata.is_dir() {
-        bail!(
-            "Server icon path '{}' is not a directory",
-            directory.display()
-        );
-    }
+    /// How often (days) the icon digest is posted, covering that period of the history.
+    summary_interval_days: u64,
 
-    let read_dir = fs::read_dir(directory)
-        .map_err(|error| eyre!("Failed to read '{directory:?}': {error}"))?;
+    /// The channel ID the daily "icon of the day" post with reaction voting goes to (unset
+    /// disables it).
+    icon_of_the_day_channel: Option<ChannelId>,
 
-    let mut paths = Vec::new();
-    for entry in read_dir {
-        let path = entry?.path();
-        if path.is_file() && is_supported_icon(&path) {
-            paths.push(path);
-        }
-    }
+    /// When (UTC, HH:MM) the icon of the day is posted; the previous post is tallied then.
+    icon_of_the_day_time: String,
 
-    Ok(paths)
-}
+    /// Consecutive server icon update failures before rotation is paused (0 disables the breaker).
+    breaker_threshold: u32,
 
-fn icon_filename(path: &Path) -> Result<String> {
-    path.file_name()
-        .and_then(|name| name.to_str())
-        .map(std::string::ToString::to_string)
-        .ok_or_else(|| eyre!("Server icon path '{path:?}' is missing a filename"))
-}
+    /// How long (minutes) rotation stays paused once the breaker trips.
+    breaker_cooldown_minutes: u64,
 
-fn move_icon_file(source: &Path, target_dir: &Path) -> Result<PathBuf> {
-    let filename = icon_filename(source)?;
-    let destination = target_dir.join(&filename);
+    /// Seed for icon selection and delay randomization, for reproducible runs (unset uses the OS RNG).
+    rng_seed: Option<u64>,
 
-    if let Some(parent) = destination.parent() {
-        fs::create_dir_all(parent)?;
-    }
+    /// Path to a log file written alongside stdout, rotated once it exceeds `log_file_max_bytes`.
+    log_file: Option<PathBuf>,
 
-    if destination.exists() {
-        fs::remove_file(&destination)?;
-    }
+    /// Maximum size (bytes) of the log file before it is rotated.
+    log_file_max_bytes: u64,
 
-    if let Err(rename_error) = fs::rename(source, &destination) {
-        fs::copy(source, &destination).and_then(|_| fs::remove_file(source)).map_err(
-            |copy_error| eyre!(
-                "Failed to move icon from '{}' to '{}': rename error: {rename_error}; copy error: {copy_error}",
-                source.display(),
-                destination.display()
-            ),
-        )?;
-    }
+    /// Number of rotated log files to keep (e.g. `bot.log.1` through `bot.log.5`).
+    log_file_max_files: usize,
 
-    Ok(destination)
-}
+    /// How long (minutes) a `/loglevel` change lasts before the default filter returns (0 keeps
+    /// it until `/loglevel default`).
+    log_level_reset_minutes: u64,
 
-fn recycle_used_icons(unused_dir: &Path, used_dir: &Path) -> Result<Vec<PathBuf>> {
-    let used_paths = load_icon_paths(used_dir)?;
-    if used_paths.is_empty() {
-        return Ok(Vec::new());
-    }
+    /// DM the owner of a guild the bot is newly added to when it isn't the configured guild.
+    notify_unconfigured_guild_owner: bool,
 
-    let mut moved = Vec::new();
-    for path in used_paths {
-        let destination = move_icon_file(&path, unused_dir)?;
-        moved.push(destination);
-    }
+    /// Leave every guild other than the configured one as soon as it is seen.
+    leave_unconfigured_guilds: bool,
 
-    Ok(moved)
-}
+    /// Additional gateway intents to request on top of the non-privileged defaults, by name (e.g. `GUILD_MEMBERS`).
+    additional_intents: Vec<String>,
 
-fn icon_delay(min_hours: u64, max_hours: u64) -> Result<Option<Duration>> {
-    if max_hours == 0 {
-        return Ok(None);
-    }
+    /// Remove every member overwrite from a video channel once its voice and video channels are
+    /// both empty.
+    sweep_empty_video_channels: bool,
 
-    if min_hours > max_hours {
-        bail!(
-            "server_icon_delay_min_hours ({}) cannot be greater than server_icon_delay_max_hours ({})",
-            min_hours,
-            max_hours
-        );
-    }
+    /// How many overwrites the sweep removes at once; serenity still waits out rate limits.
+    cleanup_concurrency: usize,
 
-    let hours = if min_hours == max_hours {
-        min_hours
-    } else {
-        let mut rng = rng();
-        rng.random_range(min_hours..=max_hours)
-    };
+    /// Also move members who join the voice channel already streaming, not only those who start
+    /// streaming once inside.
+    move_on_join_streaming: bool,
 
-    if hours == 0 {
-        Ok(None)
-    } else {
-        let seconds = hours
-            .checked_mul(3_600)
-            .ok_or_else(|| eyre!("Server icon delay is too large"))?;
-        Ok(Some(Duration::from_secs(seconds)))
+    /// Alt text for the image in icon change announcements, `{icon}` is replaced by the icon's
+    /// display name.
+    announcement_alt_text: Option<String>,
+
+    /// Icon changes within this many seconds of an announcement edit it ("changed N times")
+    /// instead of posting a new one (0 announces every change separately).
+    announcement_min_interval_seconds: u64,
+
+    /// Attach calendar event icons as spoilers in announcements, for surprise reveals.
+    spoiler_event_icons: bool,
+
+    /// Restrict /serverinfo to staff instead of everyone.
+    serverinfo_staff_only: bool,
+
+    /// Ask for a button confirmation before running destructive admin commands.
+    confirm_destructive_actions: bool,
+
+    /// Nickname the bot keeps in the guild, re-applied whenever it drifts (unset leaves it alone).
+    bot_nickname: Option<String>,
+
+    /// Server name re-applied on startup when it drifted (unset leaves it alone); `/theme apply`
+    /// renames stick until the next restart.
+    guild_name: Option<String>,
+
+    /// Prefix for the text-command fallback (e.g. `!` for `!alerts`), unset keeps slash commands
+    /// only; needs the privileged `MESSAGE_CONTENT` intent in `additional_intents`.
+    text_command_prefix: Option<String>,
+
+    /// Emoji the bot reacts with when a text command succeeded, e.g. `✅` or a custom
+    /// `<:name:id>`. Only the `alerts` text command (with `text_command_prefix`) reacts: admin
+    /// commands are slash commands, whose replies can't be reacted to, so they always stay text.
+    success_reaction: Option<String>,
+
+    /// React on success instead of also replying (needs `success_reaction`).
+    success_reaction_only: bool,
+
+    /// The staff channel ID that operational alerts are posted to.
+    log_channel: Option<ChannelId>,
+
+    /// Address the HTTP API listens on, e.g. `127.0.0.1:8080` (unset disables it).
+    http_api_address: Option<String>,
+
+    /// Bearer token required by `POST /rotate`, which stays disabled until one is set.
+    http_api_token: Option<String>,
+
+    /// The staff channel ID that every admin command invocation is logged to (unset disables it).
+    audit_channel: Option<ChannelId>,
+
+    /// Weekly UTC windows during which maintenance mode is switched on automatically.
+    maintenance_windows: Vec<MaintenanceWindow>,
+
+    /// The staff channel ID that /feedback messages are forwarded to.
+    feedback_channel: Option<ChannelId>,
+
+    /// Minimum delay (seconds) between /feedback messages from the same user.
+    feedback_cooldown_seconds: u64,
+
+    /// Minimum delay (seconds) between `/icon reroll`s in the guild, whoever runs them, so the
+    /// pool isn't cycled through by hand (0 disables it).
+    icon_reroll_cooldown_seconds: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            token: String::new(),
+            guild: GuildId::default(),
+            voice: ChannelId::default(),
+            video: ChannelId::default(),
+            voice_pairs: Vec::new(),
+            broadcast_targets: Vec::new(),
+            alerts: RoleId::default(),
+            alerts_ephemeral: true,
+            alerts_retry_attempts: 2,
+            alert_presets: BTreeMap::new(),
+            server_icons_unused: PathBuf::new(),
+            server_icons_used: PathBuf::new(),
+            icon_max_age_days: None,
+            icon_calendar_url: None,
+            icon_calendar_refresh_minutes: 15,
+            icon_flag_url: None,
+            icon_flag_field: String::from("theme"),
+            icon_flag_refresh_minutes: 5,
+            icon_flag_default: None,
+            http_timeout_seconds: 30,
+            server_icons_fallback: None,
+            follow_symlinks: true,
+            icon_backup_dir: None,
+            icon_archive_dir: None,
+            expired_icons_dir: None,
+            icon_archive_template: String::from("{date}T{time}Z_{name}"),
+            icon_git_repo: None,
+            icon_git_refresh_minutes: 60,
+            move_applied_icons: true,
+            icon_requires_approval: false,
+            approval_channel: None,
+            approval_timeout_minutes: 60,
+            avoid_last_n: 1,
+            skip_animated_without_feature: true,
+            icon_upload_attempts: 3,
+            recency_weight_days: None,
+            recycle_batch_size: None,
+            weekday_themes: BTreeMap::new(),
+            themes: BTreeMap::new(),
+            member_milestones: Vec::new(),
+            milestone_icon_hours: 24,
+            icon_poll_hours: 24,
+            convert_icons: false,
+            log_thumbnail_size: None,
+            startup_icon_behavior: StartupIconBehavior::default(),
+            server_icons_delay_min_hours: 0,
+            server_icons_delay_max_hours: 0,
+            server_icons_delay_min: None,
+            server_icons_delay_max: None,
+            history_max_entries: 100,
+            history_retention_days: 30,
+            summary_channel: None,
+            summary_interval_days: 7,
+            icon_of_the_day_channel: None,
+            icon_of_the_day_time: String::from("12:00"),
+            breaker_threshold: 3,
+            breaker_cooldown_minutes: 60,
+            rng_seed: None,
+            log_file: None,
+            log_file_max_bytes: 10 * 1024 * 1024,
+            log_file_max_files: 5,
+            log_level_reset_minutes: 30,
+            notify_unconfigured_guild_owner: false,
+            leave_unconfigured_guilds: false,
+            additional_intents: Vec::new(),
+            sweep_empty_video_channels: false,
+            cleanup_concurrency: 4,
+            move_on_join_streaming: true,
+            presence_active_template: None,
+            presence_idle_texts: Vec::new(),
+            presence_rotate_minutes: 5,
+            announcement_alt_text: None,
+            announcement_min_interval_seconds: 300,
+            spoiler_event_icons: false,
+            serverinfo_staff_only: false,
+            confirm_destructive_actions: false,
+            bot_nickname: None,
+            guild_name: None,
+            text_command_prefix: None,
+            success_reaction: None,
+            success_reaction_only: false,
+            log_channel: None,
+            http_api_address: None,
+            http_api_token: None,
+            audit_channel: None,
+            maintenance_windows: Vec::new(),
+            feedback_channel: None,
+            feedback_cooldown_seconds: 300,
+            icon_reroll_cooldown_seconds: 0,
+        }
     }
 }
 
-async fn next_icon_delay(ctx: &Context) -> Result<Option<Duration>> {
-    let data = ctx.data.read().await;
-    let Some(config) = data.get::<Config>() else {
-        return Ok(None);
-    };
+impl TypeMapKey for Config {
+    type Value = Self;
+}
 
-    icon_delay(
-        config.server_icons_delay_min_hours,
-        config.server_icons_delay_max_hours,
-    )
+/// What the bot does with the server icon on startup. A pinned icon always wins and skips the
+/// startup rotation, whichever is chosen.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum StartupIconBehavior {
+    /// Apply a fresh icon right away, then start a new delay.
+    #[default]
+    Rotate,
+    /// Keep the current icon and start a new delay.
+    Skip,
+    /// Keep the current icon until the change that was scheduled before the restart, rotating
+    /// right away when that time already passed or was never saved.
+    Resume,
 }
 
-struct Events;
+/// The last time each user sent a /feedback message, used for rate-limiting.
+struct FeedbackCooldowns;
 
-#[async_trait]
-impl EventHandler for Events {
-    async fn ready(&self, ctx: Context, data_about_bot: Ready) {
-        println!("Ready: {}", data_about_bot.user.name);
+impl TypeMapKey for FeedbackCooldowns {
+    type Value = HashMap<UserId, Instant>;
+}
 
-        let activity = ActivityData {
-            name:  String::from("with commands"),
-            kind:  ActivityType::Playing,
-            state: None,
-            url:   None,
-        };
+/// The last time each guild's icon was rerolled by hand, used for `icon_reroll_cooldown_seconds`.
+struct RerollCooldowns;
 
-        ctx.set_presence(Some(activity), OnlineStatus::Online);
+impl TypeMapKey for RerollCooldowns {
+    type Value = HashMap<GuildId, Instant>;
+}
 
-        let command =
-            CreateCommand::new("alerts").description("Toggle the alerts role for yourself");
+/// An icon that was applied to the guild.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct HistoryEntry {
+    filename:   String,
+    applied_at: Timestamp,
+}
 
-        match Command::create_global_command(&ctx.http, command).await {
-            Ok(_) => println!("Successfully registered /alerts command"),
-            Err(error) => eprintln!("Error creating command: {error}"),
-        }
+/// Drops history entries older than `retention_days` (if non-zero) and trims to `max_entries`.
+fn prune_history(
+    history: &mut VecDeque<HistoryEntry>,
+    max_entries: usize,
+    retention_days: u64,
+    now: Timestamp,
+) {
+    if retention_days > 0 {
+        let retention = i64::try_from(retention_days.saturating_mul(86_400)).unwrap_or(i64::MAX);
+        let cutoff = now.unix_timestamp().saturating_sub(retention);
+        history.retain(|entry| entry.applied_at.unix_timestamp() >= cutoff);
+    }
 
-        if let Err(error) = randomize_server_icon(&ctx).await {
-            eprintln!("Error randomizing server icon: {error}");
-        }
+    while history.len() > max_entries {
+        history.pop_front();
+    }
+}
 
-        let ctx_clone = ctx.clone();
-        tokio::spawn(async move {
-            loop {
-                let Some(delay) = (match next_icon_delay(&ctx_clone).await {
-                    Ok(delay) => delay,
-                    Err(error) => {
-                        eprintln!("Error calculating server icon delay: {error}");
-                        break;
-                    }
-                }) else {
-                    println!("Server icon delay disabled; stopping icon randomizer loop");
-                    break;
-                };
+async fn record_icon_history(ctx: &Context, filename: String) {
+    let Some(config) = get_config(ctx).await else {
+        return;
+    };
 
-                println!(
-                    "Waiting {:?} before updating server icon (range {}-{} hours)",
-                    delay,
-                    ctx_clone
-                        .data
-                        .read()
-                        .await
-                        .get::<Config>()
-                        .map(|config| config.server_icons_delay_min_hours)
-                        .unwrap_or_default(),
-                    ctx_clone
-                        .data
-                        .read()
-                        .await
-                        .get::<Config>()
-                        .map(|config| config.server_icons_delay_max_hours)
-                        .unwrap_or_default()
-                );
+    let now = Timestamp::now();
+    let result = update_state(ctx, |state| {
+        state.icon_history.push_back(HistoryEntry {
+            filename,
+            applied_at: now,
+        });
 
-                sleep(delay).await;
+        prune_history(
+            &mut state.icon_history,
+            config.history_max_entries,
+            config.history_retention_days,
+            now,
+        );
+    })
+    .await;
 
-                if let Err(error) = randomize_server_icon(&ctx_clone).await {
-                    eprintln!("Error randomizing server icon: {error}");
-                }
-            }
-        });
+    if let Err(error) = result {
+        error!("Error saving state: {error}");
     }
+}
 
-    async fn voice_state_update(&self, ctx: Context, old: Option<VoiceState>, new: VoiceState) {
-        #[allow(clippy::significant_drop_tightening)]
-        let data = ctx.data.read().await;
-        let Some(config) = data.get::<Config>() else {
+/// Posts the icon digest every `summary_interval_days` while `summary_channel` is set, counting
+/// from the last summary in the state so restarts don't push it back.
+async fn run_summary_loop(ctx: Context) {
+    loop {
+        let Some(config) = get_config(&ctx).await else {
             return;
         };
 
-        let Some(member) = new.member else {
+        if config.summary_channel.is_none() {
             return;
+        }
+
+        let last_summary = ctx
+            .data
+            .read()
+            .await
+            .get::<State>()
+            .and_then(|state| state.last_summary);
+        let Some(last_summary) = last_summary else {
+            /* The first interval starts now */
+            if let Err(error) =
+                update_state(&ctx, |state| state.last_summary = Some(Timestamp::now())).await
+            {
+                error!("Error saving state: {error}");
+                return;
+            }
+
+            continue;
         };
 
-        let Some(guild_id) = new.guild_id else {
+        let interval = config.summary_interval_days.max(1).saturating_mul(86_400);
+        let elapsed = Timestamp::now()
+            .unix_timestamp()
+            .saturating_sub(last_summary.unix_timestamp());
+        let remaining = interval.saturating_sub(u64::try_from(elapsed).unwrap_or_default());
+        sleep(Duration::from_secs(remaining)).await;
+
+        /* Re-read so a channel removed in the meantime is respected */
+        let Some(config) = get_config(&ctx).await else {
             return;
         };
 
-        if guild_id != config.guild {
-            return;
+        if let Err(error) = post_icon_summary(&ctx, &config).await {
+            error!("Error posting the icon summary: {error}");
         }
 
-        if let Some(new_channel_id) = new.channel_id {
-            /* User joined the voice channel, giving view permission */
-            if new_channel_id == config.voice {
-                println!("[{}] joined the voice channel", member.display_name());
-                println!("Giving access to the video channel");
-
-                let target = PermissionOverwrite {
-                    allow: Permissions::VIEW_CHANNEL,
-                    deny:  Permissions::empty(),
-                    kind:  PermissionOverwriteType::Member(new.user_id),
-                };
-
-                if let Err(error) = config.video.create_permission(&ctx, target).await {
-                    eprintln!("Error updating channel permissions: {error}");
-                };
-            }
-
-            /* User started streaming in the voice channel */
-            if let Some(stream) = new.self_stream {
-                if stream && new_channel_id == config.voice {
-                    let result = guild_id.move_member(&ctx, new.user_id, config.video).await;
-                    if let Err(error) = result {
-                        eprintln!("Error moving channel: {error}");
-                    }
-                }
-            }
+        if let Err(error) =
+            update_state(&ctx, |state| state.last_summary = Some(Timestamp::now())).await
+        {
+            error!("Error saving state: {error}");
+            return;
         }
+    }
+}
 
-        let Some(old) = old else {
+/// Posts the icon of the day at `icon_of_the_day_time` every day while
+/// `icon_of_the_day_channel` is set.
+async fn run_icon_of_the_day_loop(ctx: Context) {
+    loop {
+        let Some(config) = get_config(&ctx).await else {
             return;
         };
 
-        let Some(old_channel_id) = old.channel_id else {
+        if config.icon_of_the_day_channel.is_none() {
             return;
-        };
+        }
 
-        /* User left the voice/video channel, remove view permission */
-        let channels = [config.voice, config.video];
-        if channels.contains(&old_channel_id) {
-            if let Some(new_channel_id) = new.channel_id {
-                if channels.contains(&new_channel_id) {
-                    return;
-                }
-            }
+        let Ok(time) = NaiveTime::parse_from_str(&config.icon_of_the_day_time, "%H:%M") else {
+            error!(
+                "Invalid icon_of_the_day_time '{}'",
+                config.icon_of_the_day_time
+            );
+            return;
+        };
 
-            println!("[{}] left the video channel", member.display_name());
-            println!("Removing access to the video channel");
+        sleep(until_next_daily(Utc::now(), time)).await;
 
-            let permission_type = PermissionOverwriteType::Member(new.user_id);
-            let result = config.video.delete_permission(&ctx, permission_type).await;
+        /* Re-read so a channel removed in the meantime is respected */
+        let Some(config) = get_config(&ctx).await else {
+            return;
+        };
 
-            if let Err(error) = result {
-                eprintln!("Error updating channel permissions: {error}");
-            }
+        if let Err(error) = tally_icon_of_the_day(&ctx).await {
+            error!("Error tallying the icon of the day: {error}");
         }
-    }
 
-    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
-        if let Interaction::Command(command) = interaction {
-            if let Err(error) = handle_command(&ctx, &command).await {
-                eprintln!("Error handling command: {error}");
-            }
+        if let Err(error) = post_icon_of_the_day(&ctx, &config).await {
+            error!("Error posting the icon of the day: {error}");
         }
     }
 }
 
-async fn handle_command(ctx: &Context, command: &CommandInteraction) -> Result<()> {
-    #[allow(clippy::single_match_else)]
-    match command.data.name.as_str() {
-        "alerts" => handle_alerts_command(ctx, command).await?,
-        _ => {
-            let response = CreateInteractionResponse::Message(
-                CreateInteractionResponseMessage::new()
-                    .content("Unknown command")
-                    .ephemeral(true),
-            );
+/// How long until the next `time` of day (UTC), a full day when it is right now.
+fn until_next_daily(now: DateTime<Utc>, time: NaiveTime) -> Duration {
+    let today = now.date_naive().and_time(time).and_utc();
+    let next = if today > now {
+        today
+    } else {
+        today + chrono::Duration::days(1)
+    };
 
-            command.create_response(&ctx.http, response).await?;
+    (next - now).to_std().unwrap_or_default()
+}
+
+/// Adds the reactions on the previous icon of the day post to the icon's votes, without the
+/// bot's own.
+async fn tally_icon_of_the_day(ctx: &Context) -> Result<()> {
+    let previous = ctx
+        .data
+        .read()
+        .await
+        .get::<State>()
+        .and_then(|state| state.icon_of_the_day.clone());
+    let Some(previous) = previous else {
+        return Ok(());
+    };
+
+    let message = previous
+        .channel_id
+        .message(&ctx.http, previous.message_id)
+        .await;
+
+    /* A deleted post still counts as shown, only without votes */
+    let (mut up, mut down) = (0, 0);
+    match message {
+        Ok(message) => {
+            for reaction in &message.reactions {
+                let count = reaction.count.saturating_sub(u64::from(reaction.me));
+                if reaction.reaction_type.unicode_eq("👍") {
+                    up += count;
+                } else if reaction.reaction_type.unicode_eq("👎") {
+                    down += count;
+                }
+            }
         }
+        Err(error) => warn!("The previous icon of the day could not be fetched: {error}"),
     }
+
+    update_state(ctx, |state| {
+        state.icon_of_the_day = None;
+        let votes = state
+            .icon_votes
+            .entry(previous.filename.clone())
+            .or_default();
+        votes.posts += 1;
+        votes.up += up;
+        votes.down += down;
+    })
+    .await?;
+
+    info!(
+        "Tallied the icon of the day '{}': {up} up, {down} down",
+        previous.filename
+    );
+
     Ok(())
 }
 
-async fn handle_alerts_command(ctx: &Context, command: &CommandInteraction) -> Result<()> {
-    let data = ctx.data.read().await;
-    let Some(config) = data.get::<Config>() else {
-        let response = CreateInteractionResponse::Message(
-            CreateInteractionResponseMessage::new()
-                .content("Configuration not found")
-                .ephemeral(true),
-        );
-        command.create_response(&ctx.http, response).await?;
+/// Features the currently applied icon with its all-time votes and opens the reaction voting.
+async fn post_icon_of_the_day(ctx: &Context, config: &Config) -> Result<()> {
+    let Some(channel_id) = config.icon_of_the_day_channel else {
         return Ok(());
     };
 
-    let Some(guild_id) = command.guild_id else {
-        let response = CreateInteractionResponse::Message(
-            CreateInteractionResponseMessage::new()
-                .content("This command can only be used in a guild")
-                .ephemeral(true),
-        );
-        command.create_response(&ctx.http, response).await?;
+    let data = ctx.data.read().await;
+    let filename = data
+        .get::<AppliedIcon>()
+        .and_then(|applied| applied.filename.clone());
+    let votes = data
+        .get::<State>()
+        .zip(filename.as_ref())
+        .and_then(|(state, filename)| state.icon_votes.get(filename).copied())
+        .unwrap_or_default();
+    drop(data);
+
+    let Some(filename) = filename else {
+        info!("No icon has been applied yet, skipping the icon of the day");
         return Ok(());
     };
 
-    if guild_id != config.guild {
-        let response = CreateInteractionResponse::Message(
-            CreateInteractionResponseMessage::new()
-                .content("This command is not available in this guild")
-                .ephemeral(true),
-        );
-        command.create_response(&ctx.http, response).await?;
+    let Some(path) = find_icon(config, &filename) else {
+        bail!("The applied icon '{filename}' no longer exists");
+    };
+
+    let attachment = CreateAttachment::path(&path).await?;
+    let meta = load_icon_meta(&path);
+    let title = meta.title(&filename);
+    let footer = if votes.posts == 0 {
+        String::from("First time as icon of the day")
+    } else {
+        format!(
+            "Featured {} times before, 👍 {} 👎 {}",
+            votes.posts, votes.up, votes.down
+        )
+    };
+
+    let embed = CreateEmbed::new()
+        .title(format!("Icon of the day: {title}"))
+        .description("Vote with 👍 or 👎 until tomorrow's icon of the day")
+        .thumbnail(format!("attachment://{}", attachment.filename))
+        .footer(CreateEmbedFooter::new(footer))
+        .timestamp(Timestamp::now());
+    let message = CreateMessage::new().embed(embed).add_file(attachment);
+    let message = channel_id.send_message(&ctx.http, message).await?;
+
+    for emoji in ['👍', '👎'] {
+        if let Err(error) = message.react(&ctx.http, emoji).await {
+            warn!("Error adding the {emoji} vote to the icon of the day: {error}");
+        }
+    }
+
+    let post = IconOfTheDay {
+        channel_id,
+        message_id: message.id,
+        filename: filename.clone(),
+    };
+    update_state(ctx, |state| state.icon_of_the_day = Some(post)).await?;
+    info!("Posted '{filename}' as the icon of the day");
+
+    Ok(())
+}
+
+/// Lists every icon applied within the summary interval with how often it was applied, adding
+/// thumbnails for the most frequent ones that are still on disk.
+async fn post_icon_summary(ctx: &Context, config: &Config) -> Result<()> {
+    let Some(channel_id) = config.summary_channel else {
         return Ok(());
+    };
+
+    let days = config.summary_interval_days.max(1);
+    let period = i64::try_from(days.saturating_mul(86_400)).unwrap_or(i64::MAX);
+    let cutoff = Timestamp::now().unix_timestamp().saturating_sub(period);
+
+    let mut counts = BTreeMap::<String, usize>::new();
+    if let Some(state) = ctx.data.read().await.get::<State>() {
+        for entry in &state.icon_history {
+            if entry.applied_at.unix_timestamp() >= cutoff {
+                *counts.entry(entry.filename.clone()).or_default() += 1;
+            }
+        }
     }
 
-    if config.alerts.get() == 0 {
-        let response = CreateInteractionResponse::Message(
-            CreateInteractionResponseMessage::new()
-                .content("Alerts role is not configured. Please contact an administrator.")
-                .ephemeral(true),
-        );
-        command.create_response(&ctx.http, response).await?;
+    if counts.is_empty() {
+        info!("No icons were applied in the last {days} days, skipping the summary");
         return Ok(());
     }
 
-    let member = guild_id.member(&ctx.http, command.user.id).await?;
-    let has_role = member.roles.contains(&config.alerts);
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_by(|(_, first), (_, second)| second.cmp(first));
 
-    let (message, success) = if has_role {
-        match member.remove_role(&ctx.http, config.alerts).await {
-            Ok(()) => ("Successfully removed the alerts role!", true),
-            Err(_) => (
-                "Failed to remove the alerts role. Please contact an administrator.",
-                false,
-            ),
+    let total = counts.iter().map(|(_, count)| count).sum::<usize>();
+    let mut lines = String::new();
+    for (filename, count) in &counts {
+        let line = format!("`{filename}` × {count}\n");
+        if lines.len() + line.len() > 4000 {
+            lines.push('…');
+            break;
         }
-    } else {
-        match member.add_role(&ctx.http, config.alerts).await {
-            Ok(()) => ("Successfully added the alerts role!", true),
-            Err(_) => (
-                "Failed to add the alerts role. Please contact an administrator.",
-                false,
-            ),
-        }
-    };
 
-    let response = CreateInteractionResponse::Message(
-        CreateInteractionResponseMessage::new()
-            .content(message)
-            .ephemeral(true),
+        lines.push_str(&line);
+    }
+
+    let mut message = CreateMessage::new().embed(
+        CreateEmbed::new()
+            .title(format!("Server icons of the last {days} days"))
+            .description(lines)
+            .footer(CreateEmbedFooter::new(format!(
+                "{total} changes, {} icons",
+                counts.len()
+            )))
+            .timestamp(Timestamp::now()),
     );
-    command.create_response(&ctx.http, response).await?;
 
-    if success {
-        let action = if has_role { "removed" } else { "added" };
-        println!("[{}] {} the alerts role", command.user.name, action);
+    /* A message holds at most ten embeds, one of which is the list above */
+    for (filename, count) in counts.iter().take(9) {
+        let Some(path) = find_icon(config, filename) else {
+            continue;
+        };
+
+        let Ok(attachment) = CreateAttachment::path(&path).await else {
+            continue;
+        };
+
+        let embed = CreateEmbed::new()
+            .title(filename)
+            .description(format!("Applied {count} times"))
+            .thumbnail(format!("attachment://{}", attachment.filename));
+        message = message.embed(embed).add_file(attachment);
     }
 
+    channel_id.send_message(&ctx.http, message).await?;
+    info!("Posted the icon summary of the last {days} days");
+
     Ok(())
 }
 
-async fn randomize_server_icon(ctx: &Context) -> Result<()> {
-    let (guild_id, unused_dir, used_dir) = {
-        let data = ctx.data.read().await;
-        let Some(config) = data.get::<Config>() else {
-            return Ok(());
-        };
+/// Pauses icon rotation after repeated guild edit failures, e.g. when the bot lost its permissions.
+#[derive(Clone, Copy, Default)]
+struct IconBreaker {
+    failures:     u32,
+    paused_until: Option<Instant>,
+}
 
-        if config.server_icons_unused.as_os_str().is_empty() {
-            return Ok(());
+impl IconBreaker {
+    fn remaining_pause(&self, now: Instant) -> Option<Duration> {
+        self.paused_until
+            .and_then(|until| until.checked_duration_since(now))
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    /// Whether rotation is paused, closing the breaker again once the cooldown has passed.
+    fn is_paused(&mut self, now: Instant) -> bool {
+        if self.remaining_pause(now).is_some() {
+            return true;
         }
 
-        (
-            config.guild,
-            config.server_icons_unused.clone(),
-            config.server_icons_used.clone(),
-        )
-    };
+        self.paused_until = None;
+        false
+    }
 
-    let mut icon_paths = match load_icon_paths(&unused_dir) {
-        Ok(paths) => paths,
-        Err(error) => {
-            let io_denied = error
-                .downcast_ref::<io::Error>()
-                .is_some_and(|io_error| io_error.kind() == io::ErrorKind::PermissionDenied);
+    fn describe(&self, now: Instant) -> String {
+        self.remaining_pause(now).map_or_else(
+            || format!("closed ({} consecutive failures)", self.failures),
+            |remaining| {
+                format!(
+                    "open, rotation paused for {} more minutes ({} consecutive failures)",
+                    remaining.as_secs() / 60 + 1,
+                    self.failures
+                )
+            },
+        )
+    }
 
-            if io_denied {
-                eprintln!(
-                    "Server icon path '{}' is not readable: {error}",
-                    unused_dir.display()
-                );
-                return Ok(());
-            }
+    const fn record_success(&mut self) {
+        self.failures = 0;
+        self.paused_until = None;
+    }
 
-            return Err(error);
+    /// Counts a failure, returning true when it trips the breaker.
+    fn record_failure(&mut self, threshold: u32, cooldown: Duration, now: Instant) -> bool {
+        self.failures = self.failures.saturating_add(1);
+        if threshold == 0 || self.failures < threshold || self.paused_until.is_some() {
+            return false;
         }
+
+        self.paused_until = Some(now + cooldown);
+        true
+    }
+}
+
+impl TypeMapKey for IconBreaker {
+    type Value = Self;
+}
+
+async fn icon_breaker_paused(ctx: &Context) -> bool {
+    ctx.data
+        .write()
+        .await
+        .entry::<IconBreaker>()
+        .or_default()
+        .is_paused(Instant::now())
+}
+
+async fn icon_breaker_remaining(ctx: &Context) -> Option<Duration> {
+    ctx.data
+        .read()
+        .await
+        .get::<IconBreaker>()
+        .and_then(|breaker| breaker.remaining_pause(Instant::now()))
+}
+
+async fn record_icon_failure(ctx: &Context, error: &SerenityError) {
+    let Some(config) = get_config(ctx).await else {
+        return;
     };
 
-    if icon_paths.is_empty() {
-        println!(
-            "Server icon directory '{}' is empty, recycling used icons from '{}'",
-            unused_dir.display(),
-            used_dir.display()
+    let cooldown = Duration::from_secs(config.breaker_cooldown_minutes.saturating_mul(60));
+    let mut data = ctx.data.write().await;
+    let breaker = data.entry::<IconBreaker>().or_default();
+    let tripped = breaker.record_failure(config.breaker_threshold, cooldown, Instant::now());
+    let failures = breaker.failures;
+    drop(data);
+
+    if tripped {
+        let content = format!(
+            "Server icon rotation paused for {} minutes after {failures} consecutive failures to update the server icon. Last error: {error}",
+            config.breaker_cooldown_minutes
         );
 
-        icon_paths = match recycle_used_icons(&unused_dir, &used_dir) {
-            Ok(paths) => paths,
-            Err(error) => {
-                let io_denied = error
-                    .downcast_ref::<io::Error>()
-                    .is_some_and(|io_error| io_error.kind() == io::ErrorKind::PermissionDenied);
+        error!("{content}");
+        notify_staff(ctx, &config, content).await;
+    }
+}
 
-                if io_denied {
-                    eprintln!(
-                        "Server icon path '{}' is not readable: {error}",
-                        used_dir.display()
-                    );
-                    return Ok(());
-                }
+/// Posts an operational alert to the log channel, if one is configured.
+async fn notify_staff(ctx: &Context, config: &Config, content: impl Into<String>) {
+    let Some(channel_id) = config.log_channel else {
+        return;
+    };
 
-                return Err(error);
-            }
+    let message = CreateMessage::new()
+        .content(content)
+        .allowed_mentions(CreateAllowedMentions::new());
+
+    if let Err(error) = channel_id.send_message(&ctx.http, message).await {
+        error!("Error posting to the log channel: {error}");
+    }
+}
+
+/// Whether maintenance mode is on, pausing icon rotation and voice channel automation.
+struct Maintenance;
+
+impl TypeMapKey for Maintenance {
+    type Value = bool;
+}
+
+async fn maintenance_active(ctx: &Context) -> bool {
+    ctx.data
+        .read()
+        .await
+        .get::<Maintenance>()
+        .copied()
+        .unwrap_or_default()
+}
+
+/// A weekly maintenance window in UTC, e.g. `{ day = "sunday", start = "02:00", end = "03:00" }`;
+/// an end before the start runs into the next day.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct MaintenanceWindow {
+    day:   String,
+    start: String,
+    end:   String,
+}
+
+impl MaintenanceWindow {
+    /// The start and end as minutes since Monday 00:00.
+    fn minutes_of_week(&self) -> Result<(u32, u32)> {
+        let day = self
+            .day
+            .parse::<Weekday>()
+            .map_err(|_| eyre!("'{}' is not a day of the week", self.day))?;
+        let time = |value: &str| {
+            NaiveTime::parse_from_str(value, "%H:%M")
+                .map(|time| time.hour() * 60 + time.minute())
+                .map_err(|_| eyre!("'{value}' is not a HH:MM time"))
         };
+
+        let offset = day.num_days_from_monday() * 1440;
+        let start = offset + time(&self.start)?;
+        let mut end = offset + time(&self.end)?;
+        if end <= start {
+            end += 1440;
+        }
+
+        Ok((start, end))
     }
 
-    if icon_paths.is_empty() {
-        println!(
-            "Server icon directory '{}' is empty or contains no supported images",
-            unused_dir.display()
+    fn contains(&self, now: DateTime<Utc>) -> bool {
+        let Ok((start, end)) = self.minutes_of_week() else {
+            return false;
+        };
+
+        /* Windows late on Sunday run past the end of the week */
+        let now = now.weekday().num_days_from_monday() * 1440 + now.hour() * 60 + now.minute();
+        (start..end).contains(&now) || (start..end).contains(&(now + 7 * 1440))
+    }
+}
+
+/// Whether a scheduled maintenance window was active at the last check, and whether it was the
+/// one that switched maintenance mode on.
+#[derive(Clone, Copy, Default)]
+struct ScheduledMaintenance {
+    window_active: bool,
+    switched_on:   bool,
+}
+
+impl TypeMapKey for ScheduledMaintenance {
+    type Value = Self;
+}
+
+async fn maintenance_window_active(ctx: &Context) -> bool {
+    ctx.data
+        .read()
+        .await
+        .get::<ScheduledMaintenance>()
+        .is_some_and(|scheduled| scheduled.window_active)
+}
+
+/// Switches maintenance mode on when a window opens and off when it closes, leaving maintenance
+/// that was already on, or changed with `/maintenance` in between, alone.
+async fn run_maintenance_schedule_loop(ctx: Context) {
+    loop {
+        let Some(config) = get_config(&ctx).await else {
+            return;
+        };
+
+        if config.maintenance_windows.is_empty() {
+            return;
+        }
+
+        let now = Utc::now();
+        let active = config
+            .maintenance_windows
+            .iter()
+            .any(|window| window.contains(now));
+
+        let mut data = ctx.data.write().await;
+        let scheduled = data
+            .get::<ScheduledMaintenance>()
+            .copied()
+            .unwrap_or_default();
+        let manual =
+            data.get::<Maintenance>().copied().unwrap_or_default() && !scheduled.switched_on;
+        let content = if active == scheduled.window_active {
+            None
+        } else if active && !manual {
+            data.insert::<Maintenance>(true);
+            Some("A scheduled maintenance window started, automation is paused")
+        } else if !active && scheduled.switched_on {
+            data.insert::<Maintenance>(false);
+            Some("The scheduled maintenance window ended, automation resumed")
+        } else {
+            None
+        };
+
+        if active != scheduled.window_active {
+            data.insert::<ScheduledMaintenance>(ScheduledMaintenance {
+                window_active: active,
+                switched_on:   active && !manual,
+            });
+        }
+        drop(data);
+
+        if let Some(content) = content {
+            info!("{content}");
+            notify_staff(&ctx, &config, content).await;
+        }
+
+        sleep(Duration::from_mins(1)).await;
+    }
+}
+
+/// Fills in the `icon_archive_template` placeholders, failing on unknown or unclosed ones.
+fn render_archive_name(template: &str, filename: &str, now: DateTime<Utc>) -> Result<String> {
+    let path = Path::new(filename);
+    let stem = path.file_stem().and_then(OsStr::to_str).unwrap_or(filename);
+    let ext = path.extension().and_then(OsStr::to_str).unwrap_or_default();
+
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| eyre!("unclosed placeholder in '{template}'"))?;
+
+        let value = match &rest[start + 1..start + end] {
+            "date" => now.format("%Y-%m-%d").to_string(),
+            "time" => now.format("%H%M%S").to_string(),
+            "epoch" => now.timestamp().to_string(),
+            "name" => filename.to_string(),
+            "stem" => stem.to_string(),
+            "ext" => ext.to_string(),
+            other => bail!("unknown placeholder '{{{other}}}' in '{template}'"),
+        };
+
+        rendered.push_str(&value);
+        rest = &rest[start + end + 1..];
+    }
+
+    rendered.push_str(rest);
+    if matches!(rendered.as_str(), "" | "." | "..") || rendered.contains(['/', '\\']) {
+        bail!("'{template}' must render to a plain filename");
+    }
+
+    Ok(rendered)
+}
+
+/// Copies the applied icon into the archive under its templated name.
+fn archive_icon(config: &Config, archive_dir: &Path, path: &Path, filename: &str) -> Result<()> {
+    let name = render_archive_name(&config.icon_archive_template, filename, Utc::now())?;
+    fs::create_dir_all(archive_dir)?;
+    fs::copy(path, archive_dir.join(&name))?;
+    debug!("Archived server icon '{filename}' as '{name}'");
+    Ok(())
+}
+
+/// Rejects overlapping voice pairs, maintenance windows, weekday themes and the icon of the day
+/// with an unknown day or a malformed time, delay durations that don't parse, archive templates
+/// that don't render and modes missing what they need.
+fn validate_config_values(config: &Config) -> Result<()> {
+    for window in &config.maintenance_windows {
+        window
+            .minutes_of_week()
+            .map_err(|error| eyre!("Invalid maintenance window: {error}"))?;
+    }
+
+    for day in config.weekday_themes.keys() {
+        if day.parse::<Weekday>().is_err() {
+            bail!("Invalid weekday_themes entry: '{day}' is not a day of the week");
+        }
+    }
+
+    /* A channel in two pairs would only ever be handled as part of the first */
+    let (mut pairs, mut seen) = (HashSet::new(), HashSet::new());
+    for pair in voice_pairs(config) {
+        /* Listing the legacy pair again is harmless */
+        if !pairs.insert(pair) {
+            continue;
+        }
+
+        if pair.voice == pair.video {
+            bail!(
+                "Invalid voice pair: {} is both its voice and video channel",
+                pair.voice
+            );
+        }
+
+        for channel_id in [pair.voice, pair.video] {
+            if !seen.insert(channel_id) {
+                bail!("Invalid voice pairs: channel {channel_id} belongs to more than one pair");
+            }
+        }
+    }
+
+    config.icon_delay_range()?;
+
+    if config.icon_of_the_day_channel.is_some()
+        && NaiveTime::parse_from_str(&config.icon_of_the_day_time, "%H:%M").is_err()
+    {
+        bail!(
+            "Invalid icon_of_the_day_time: '{}' is not a HH:MM time",
+            config.icon_of_the_day_time
         );
-        return Ok(());
     }
 
-    let selected_icon = {
-        let mut rng = rng();
-        icon_paths
-            .choose(&mut rng)
-            .cloned()
-            .ok_or_else(|| eyre!("Failed to select a server icon"))?
+    if config.icon_archive_dir.is_some() {
+        render_archive_name(&config.icon_archive_template, "icon.png", Utc::now())
+            .map_err(|error| eyre!("Invalid icon_archive_template: {error}"))?;
+    }
+
+    validate_mode_requirements(config)
+}
+
+/// Rejects modes that are enabled without what they need, which would otherwise quietly fall
+/// back to other behavior at runtime, listing every problem at once. There is no single
+/// `icon_selection_mode`; these are the options that switch behavior on.
+fn validate_mode_requirements(config: &Config) -> Result<()> {
+    let mut problems = Vec::new();
+
+    if config.icon_requires_approval
+        && config.approval_channel.is_none()
+        && config.log_channel.is_none()
+    {
+        problems.push("icon_requires_approval needs approval_channel or log_channel");
+    }
+
+    if config.success_reaction_only && config.success_reaction.is_none() {
+        problems.push("success_reaction_only needs success_reaction");
+    }
+
+    if config
+        .success_reaction
+        .as_deref()
+        .is_some_and(|emoji| emoji.parse::<ReactionType>().is_err())
+    {
+        problems.push("success_reaction is not a valid emoji");
+    }
+
+    if config.weekday_themes.values().any(String::is_empty) {
+        problems.push("weekday_themes entries need a theme name");
+    }
+
+    if config.http_api_address.is_none() && config.http_api_token.is_some() {
+        problems.push("http_api_token needs http_api_address");
+    }
+
+    if !cfg!(feature = "convert") {
+        if config.convert_icons {
+            problems.push("convert_icons needs a build with the `convert` feature");
+        }
+
+        if config.log_thumbnail_size.is_some() {
+            problems.push("log_thumbnail_size needs a build with the `convert` feature");
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        bail!("Invalid config: {}", problems.join(", "))
+    }
+}
+
+/// An admin action that can be guarded behind a confirmation button.
+enum DestructiveAction {
+    RemoveIcon(String),
+    MaintenanceOn,
+}
+
+impl DestructiveAction {
+    fn describe(&self) -> String {
+        match self {
+            Self::RemoveIcon(filename) => format!("delete the icon '{filename}'"),
+            Self::MaintenanceOn => String::from("enable maintenance mode"),
+        }
+    }
+}
+
+/// How long a confirmation button stays valid.
+const CONFIRMATION_TIMEOUT: Duration = Duration::from_mins(5);
+
+struct PendingConfirmation {
+    user_id:    UserId,
+    action:     DestructiveAction,
+    created_at: Instant,
+}
+
+/// Destructive actions awaiting confirmation, keyed by the ID of the interaction that requested them.
+struct PendingConfirmations;
+
+impl TypeMapKey for PendingConfirmations {
+    type Value = HashMap<String, PendingConfirmation>;
+}
+
+/// A voice channel whose members get access to, and are moved into when streaming, its video
+/// channel.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+struct VoicePair {
+    voice: ChannelId,
+    video: ChannelId,
+}
+
+/// A partner server that icon changes are shared with, each part done only when set.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+struct BroadcastTarget {
+    /// The channel ID the announcement is posted to.
+    channel: Option<ChannelId>,
+    /// The guild ID the same icon is applied to, which needs Manage Server there.
+    guild:   Option<GuildId>,
+}
+
+/// Every configured voice and video pair, starting with `voice` and `video` when set.
+fn voice_pairs(config: &Config) -> Vec<VoicePair> {
+    let legacy = VoicePair {
+        voice: config.voice,
+        video: config.video,
     };
 
-    let icon_name = icon_filename(&selected_icon)?;
-    let attachment = CreateAttachment::path(&selected_icon).await?;
-    let builder = EditGuild::new().icon(Some(&attachment));
+    std::iter::once(legacy)
+        .filter(|pair| pair.voice != ChannelId::default())
+        .chain(config.voice_pairs.iter().copied())
+        .collect()
+}
 
-    guild_id.edit(&ctx.http, builder).await?;
-    move_icon_file(&selected_icon, &used_dir)?;
-    println!(
-        "Updated server icon to '{}' from '{}'",
-        icon_name,
-        selected_icon.display()
-    );
+/// The pair that the channel is the voice or video channel of.
+fn find_voice_pair(pairs: &[VoicePair], channel_id: ChannelId) -> Option<VoicePair> {
+    pairs
+        .iter()
+        .copied()
+        .find(|pair| pair.voice == channel_id || pair.video == channel_id)
+}
 
-    Ok(())
+/// What a voice state update means for moving the member into the pair's video channel.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum StreamMove {
+    /// Not streaming in the pair's voice channel.
+    Stay,
+    /// Streaming in the voice channel, after starting the stream there or joining with it.
+    Move,
+    /// Joined the voice channel already streaming while `move_on_join_streaming` is off.
+    JoinedStreaming,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    color_eyre::install()?;
+/// Decides whether the voice state update moves the member into the pair's video channel.
+fn stream_move(
+    old_channel_id: Option<ChannelId>,
+    new_channel_id: ChannelId,
+    self_stream: Option<bool>,
+    pair: VoicePair,
+    move_on_join_streaming: bool,
+) -> StreamMove {
+    if new_channel_id != pair.voice || self_stream != Some(true) {
+        return StreamMove::Stay;
+    }
 
-    let config = Config::load().unwrap_or_default();
-    if config.token.is_empty() {
-        eprintln!("You must provide a Discord token in the config file");
-        config.save()?;
-        return Ok(());
+    if old_channel_id != Some(pair.voice) && !move_on_join_streaming {
+        return StreamMove::JoinedStreaming;
     }
 
-    let intents = GatewayIntents::non_privileged();
-    let mut client = Client::builder(&config.token, intents)
-        .event_handler(Events)
-        .await?;
+    StreamMove::Move
+}
+
+/// Whether the overwrite is the view grant given on joining the voice channel, as opposed to one
+/// staff set by hand, such as a deny keeping a member out.
+fn is_video_grant(overwrite: &PermissionOverwrite) -> bool {
+    overwrite.allow == Permissions::VIEW_CHANNEL && overwrite.deny.is_empty()
+}
 
-    client.data.write().await.insert::<Config>(config);
+/// Removes every lingering view grant from the pair's video channel once nobody is left in
+/// either channel, using the cached voice states and overwrites; `left` was already removed.
+async fn sweep_empty_video_channel(
+    ctx: &Context,
+    config: &Config,
+    guild_id: GuildId,
+    pair: VoicePair,
+    left: UserId,
+) {
+    let lingering = {
+        let Some(guild) = ctx.cache.guild(guild_id) else {
+            return;
+        };
 
-    println!("Starting...");
-    client.start().await?;
-    bail!("Unreachable")
+        let occupied = guild.voice_states.values().any(|state| {
+            state
+                .channel_id
+                .is_some_and(|channel_id| channel_id == pair.voice || channel_id == pair.video)
+        });
+        if occupied {
+            return;
+        }
+
+        guild
+            .channels
+            .get(&pair.video)
+            .map(|channel| {
+                channel
+                    .permission_overwrites
+                    .iter()
+                    .filter(|overwrite| is_video_grant(overwrite))
+                    .filter_map(|overwrite| match overwrite.kind {
+                        PermissionOverwriteType::Member(user_id) if user_id != left => {
+                            Some(user_id)
+                        }
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    };
+
+    if lingering.is_empty() {
+        return;
+    }
+
+    info!(
+        "Voice channels are empty, removing {} lingering video channel overwrites",
+        lingering.len()
+    );
+
+    let started = Instant::now();
+    let removed = stream::iter(lingering)
+        .map(|user_id| async move {
+            let permission_type = PermissionOverwriteType::Member(user_id);
+            pair.video.delete_permission(&ctx, permission_type).await
+        })
+        .buffer_unordered(config.cleanup_concurrency.max(1))
+        .filter_map(|result| async move {
+            result
+                .inspect_err(|error| error!("Error updating channel permissions: {error}"))
+                .ok()
+        })
+        .count()
+        .await;
+
+    info!(
+        "Removed {removed} video channel overwrites in {:?}",
+        started.elapsed()
+    );
+}
+
+impl Config {
+    /// Whether applied icons are moved to the used directory, which a Git clone never allows.
+    const fn moves_icons(&self) -> bool {
+        self.move_applied_icons && self.icon_git_repo.is_none()
+    }
+
+    /// The icon delay range, from the duration strings where set and the hour fields otherwise.
+    fn icon_delay_range(&self) -> Result<(Duration, Duration)> {
+        let parse = |field: &str, value: Option<&String>, hours: u64| {
+            value.map_or_else(
+                || Ok(Duration::from_secs(hours.saturating_mul(3_600))),
+                |value| {
+                    humantime::parse_duration(value)
+                        .map_err(|error| eyre!("Invalid {field} '{value}': {error}"))
+                },
+            )
+        };
+
+        Ok((
+            parse(
+                "server_icons_delay_min",
+                self.server_icons_delay_min.as_ref(),
+                self.server_icons_delay_min_hours,
+            )?,
+            parse(
+                "server_icons_delay_max",
+                self.server_icons_delay_max.as_ref(),
+                self.server_icons_delay_max_hours,
+            )?,
+        ))
+    }
+}
+
+/// Runs git with the arguments, failing with its stderr when it exits unsuccessfully.
+async fn run_git(args: &[&OsStr]) -> Result<()> {
+    let output = tokio::process::Command::new("git")
+        .args(args)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    Ok(())
+}
+
+/// Clones `icon_git_repo` into the unused directory, or pulls it when already cloned; on failure
+/// the last good clone is kept as is.
+async fn sync_icon_repo(config: &Config) -> Result<()> {
+    let Some(repo) = config
+        .icon_git_repo
+        .as_deref()
+        .filter(|repo| !repo.is_empty())
+    else {
+        return Ok(());
+    };
+
+    let directory = &config.server_icons_unused;
+    if directory.join(".git").exists() {
+        run_git(&[
+            OsStr::new("-C"),
+            directory.as_os_str(),
+            OsStr::new("pull"),
+            OsStr::new("--ff-only"),
+        ])
+        .await?;
+        info!("Pulled the icon repository into '{}'", directory.display());
+        return Ok(());
+    }
+
+    let is_empty = fs::read_dir(directory).map_or(true, |mut entries| entries.next().is_none());
+    if !is_empty {
+        bail!(
+            "'{}' is not empty and not a clone of the icon repository",
+            directory.display()
+        );
+    }
+
+    run_git(&[
+        OsStr::new("clone"),
+        OsStr::new("--depth"),
+        OsStr::new("1"),
+        OsStr::new(repo),
+        directory.as_os_str(),
+    ])
+    .await?;
+    info!("Cloned the icon repository into '{}'", directory.display());
+    Ok(())
+}
+
+/// Pulls the icon repository every `icon_git_refresh_minutes`.
+async fn run_icon_repo_loop(ctx: Context) {
+    loop {
+        let Some(config) = get_config(&ctx).await else {
+            return;
+        };
+
+        if config.icon_git_repo.is_none() {
+            return;
+        }
+
+        let refresh = config.icon_git_refresh_minutes.max(1);
+        sleep(Duration::from_secs(refresh.saturating_mul(60))).await;
+
+        if let Err(error) = sync_icon_repo(&config).await {
+            warn!("Error pulling the icon repository, keeping the last clone: {error}");
+        }
+    }
+}
+
+/// A coordinated look for the guild; every part is optional and left alone when unset.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+struct ThemePack {
+    /// Filename of an icon in the unused or used directory.
+    icon:   Option<String>,
+    /// Path to the banner image, which needs the guild's BANNER feature.
+    banner: Option<PathBuf>,
+    /// The guild name.
+    name:   Option<String>,
+}
+
+impl ThemePack {
+    fn describe(&self) -> String {
+        let parts = [
+            self.icon.as_ref().map(|icon| format!("icon `{icon}`")),
+            self.banner
+                .as_ref()
+                .map(|banner| format!("banner `{}`", banner.display())),
+            self.name.as_ref().map(|name| format!("name **{name}**")),
+        ];
+
+        let parts = parts.into_iter().flatten().collect::<Vec<_>>();
+        if parts.is_empty() {
+            String::from("nothing")
+        } else {
+            parts.join(", ")
+        }
+    }
+}
+
+/// An icon to celebrate the guild reaching a member count.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct MemberMilestone {
+    members: u64,
+    icon:    String,
+}
+
+/// Bot state that survives restarts, saved next to the config file.
+#[derive(Clone, Debug, Default, DeriveJsonConfig, Deserialize, Serialize)]
+#[serde(default)]
+struct State {
+    /// Filename of the icon pinned with `/icon pin`, which stops the rotation until unpinned.
+    pinned_icon: Option<String>,
+
+    /// Members who used `/video optout` and are never moved to the video channel.
+    video_opt_outs: BTreeSet<UserId>,
+
+    /// The `/icon poll` that decides the next icon once it closes.
+    icon_poll: Option<IconPoll>,
+
+    /// When each icon was last applied, by filename; new files, and entries older than
+    /// `recency_weight_days`, are absent and count as never shown.
+    last_shown: BTreeMap<String, Timestamp>,
+
+    /// Filenames of the most recently applied icons, newest first, bounded by `avoid_last_n`.
+    recent_icons: VecDeque<String>,
+
+    /// How each member wants alerts delivered, set with `/alerts prefs`; absent means mentions.
+    alert_prefs: BTreeMap<UserId, AlertDelivery>,
+
+    /// Filenames Discord refused as a server icon, skipped by the rotation until removed here or
+    /// cleared with `/icon validate retry_rejected`.
+    rejected_icons: BTreeSet<String>,
+
+    /// When the rotation loop planned to change the icon next, used by the `resume` startup.
+    next_icon_change: Option<Timestamp>,
+
+    /// The highest member milestone already reached, so it is only celebrated once.
+    last_milestone: Option<u64>,
+
+    /// When the milestone icon hands back to the rotation.
+    milestone_until: Option<Timestamp>,
+
+    /// The latest icon of the day post, whose reactions are tallied when the next one goes out.
+    icon_of_the_day: Option<IconOfTheDay>,
+
+    /// Reaction votes each icon collected as icon of the day, by filename.
+    icon_votes: BTreeMap<String, IconVotes>,
+
+    /// The applied icons, oldest first, bounded by `history_max_entries` and
+    /// `history_retention_days`.
+    icon_history: VecDeque<HistoryEntry>,
+
+    /// When the last icon summary went out, or its first interval started.
+    last_summary: Option<Timestamp>,
+}
+
+/// How a member prefers to be notified by announcement tooling that reads the state file.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum AlertDelivery {
+    /// Role mentions in the announcement channels.
+    #[default]
+    Mentions,
+    /// A direct message per announcement.
+    Dm,
+    /// Keep the roles without being pinged.
+    None,
+}
+
+impl AlertDelivery {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "mentions" => Some(Self::Mentions),
+            "dm" => Some(Self::Dm),
+            "none" => Some(Self::None),
+            _ => None,
+        }
+    }
+
+    const fn describe(self) -> &'static str {
+        match self {
+            Self::Mentions => "role mentions",
+            Self::Dm => "direct messages",
+            Self::None => "no notifications",
+        }
+    }
+}
+
+/// A community poll over candidate icons, with one answer per filename in order.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct IconPoll {
+    channel_id: ChannelId,
+    message_id: MessageId,
+    options:    Vec<String>,
+}
+
+/// An icon of the day post, voted on with reactions until the next one.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct IconOfTheDay {
+    channel_id: ChannelId,
+    message_id: MessageId,
+    filename:   String,
+}
+
+/// The reaction votes an icon collected over all its icon of the day posts.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+struct IconVotes {
+    posts: u64,
+    up:    u64,
+    down:  u64,
+}
+
+impl TypeMapKey for State {
+    type Value = Self;
+}
+
+/// Applies the change to the persisted state and saves it.
+async fn update_state(ctx: &Context, update: impl FnOnce(&mut State)) -> Result<()> {
+    let mut data = ctx.data.write().await;
+    let state = data.entry::<State>().or_default();
+    update(state);
+    let state = state.clone();
+    drop(data);
+
+    state.save()?;
+    Ok(())
+}
+
+async fn video_opted_out(ctx: &Context, user_id: UserId) -> bool {
+    ctx.data
+        .read()
+        .await
+        .get::<State>()
+        .is_some_and(|state| state.video_opt_outs.contains(&user_id))
+}
+
+async fn milestone_icon_active(ctx: &Context) -> bool {
+    ctx.data
+        .read()
+        .await
+        .get::<State>()
+        .and_then(|state| state.milestone_until)
+        .is_some_and(|until| until.unix_timestamp() > Timestamp::now().unix_timestamp())
+}
+
+/// Applies the icon of a newly reached member milestone; the first check only records the
+/// milestones already passed, so a restart doesn't celebrate them again.
+async fn check_member_milestone(
+    ctx: &Context,
+    config: &Config,
+    member_count: Option<u64>,
+) -> Result<()> {
+    if config.member_milestones.is_empty() {
+        return Ok(());
+    }
+
+    let member_count = match member_count {
+        Some(member_count) => member_count,
+        None => config
+            .guild
+            .to_partial_guild_with_counts(&ctx.http)
+            .await?
+            .approximate_member_count
+            .unwrap_or_default(),
+    };
+
+    let Some(milestone) = config
+        .member_milestones
+        .iter()
+        .filter(|milestone| milestone.members <= member_count)
+        .max_by_key(|milestone| milestone.members)
+    else {
+        return Ok(());
+    };
+
+    let last = ctx
+        .data
+        .read()
+        .await
+        .get::<State>()
+        .and_then(|state| state.last_milestone);
+
+    if last.is_some_and(|last| last >= milestone.members) {
+        return Ok(());
+    }
+
+    let members = milestone.members;
+    if last.is_none() {
+        return update_state(ctx, |state| state.last_milestone = Some(members)).await;
+    }
+
+    let Some(path) = find_icon(config, &milestone.icon) else {
+        warn!("Milestone icon '{}' was not found", milestone.icon);
+        return update_state(ctx, |state| state.last_milestone = Some(members)).await;
+    };
+
+    info!("Reached {members} members, applying '{}'", milestone.icon);
+    let icon = IconCandidate::load(path);
+    let attachment = icon_attachment(&icon.path, config.convert_icons).await?;
+    let lock = icon_lock(ctx).await;
+    let guard = lock.lock().await;
+    apply_server_icon(ctx, config, &icon, &attachment, IconSource::Milestone).await?;
+    drop(guard);
+
+    let hours = i64::try_from(config.milestone_icon_hours).unwrap_or(i64::MAX / 3600);
+    let until =
+        Timestamp::from_unix_timestamp(Timestamp::now().unix_timestamp() + hours * 3600).ok();
+    update_state(ctx, |state| {
+        state.last_milestone = Some(members);
+        state.milestone_until = until;
+    })
+    .await
+}
+
+async fn pinned_icon(ctx: &Context) -> Option<String> {
+    ctx.data
+        .read()
+        .await
+        .get::<State>()
+        .and_then(|state| state.pinned_icon.clone())
+}
+
+/// Names of the background tasks that are already running, so reconnects don't spawn duplicates.
+struct BackgroundTasks;
+
+impl TypeMapKey for BackgroundTasks {
+    type Value = HashSet<&'static str>;
+}
+
+/// Marks the task as running, returning false if it already was.
+async fn start_background_task(ctx: &Context, name: &'static str) -> bool {
+    ctx.data
+        .write()
+        .await
+        .entry::<BackgroundTasks>()
+        .or_default()
+        .insert(name)
+}
+
+/// A calendar event read from an iCalendar feed.
+#[derive(Clone, Debug)]
+struct CalendarEvent {
+    summary: String,
+    start:   DateTime<Utc>,
+    end:     DateTime<Utc>,
+}
+
+/// Parses an iCalendar date or date-time value; floating and `TZID` times are treated as UTC.
+fn parse_ical_datetime(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim_end_matches('Z');
+    if let Ok(datetime) = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S") {
+        return Some(datetime.and_utc());
+    }
+
+    NaiveDate::parse_from_str(value, "%Y%m%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|datetime| datetime.and_utc())
+}
+
+/// A `VEVENT` that is still being parsed.
+#[derive(Default)]
+struct EventDraft {
+    summary: Option<String>,
+    start:   Option<DateTime<Utc>>,
+    end:     Option<DateTime<Utc>>,
+    all_day: bool,
+}
+
+impl EventDraft {
+    /// All-day events without a `DTEND` last one day, others are instantaneous.
+    fn finish(self) -> Option<CalendarEvent> {
+        let start = self.start?;
+        let end = self.end.unwrap_or(if self.all_day {
+            start + chrono::Duration::days(1)
+        } else {
+            start
+        });
+
+        Some(CalendarEvent {
+            summary: self.summary?,
+            start,
+            end,
+        })
+    }
+}
+
+/// Parses the `VEVENT`s of an iCalendar feed (recurrence rules are not expanded).
+fn parse_ical_events(text: &str) -> Vec<CalendarEvent> {
+    /* Unfold continuation lines, which start with a space or tab */
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ => lines.push(line.trim_end_matches('\r').to_string()),
+        }
+    }
+
+    let mut events = Vec::new();
+    let mut current: Option<EventDraft> = None;
+
+    for line in lines {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+
+        let (name, params) = key.split_once(';').unwrap_or((key, ""));
+        match (name.to_ascii_uppercase().as_str(), current.as_mut()) {
+            ("BEGIN", None) if value.eq_ignore_ascii_case("VEVENT") => {
+                current = Some(EventDraft::default());
+            }
+            ("END", Some(_)) if value.eq_ignore_ascii_case("VEVENT") => {
+                if let Some(event) = current.take().and_then(EventDraft::finish) {
+                    events.push(event);
+                }
+            }
+            ("SUMMARY", Some(event)) => {
+                event.summary = Some(value.replace("\\,", ",").replace("\\;", ";"));
+            }
+            ("DTSTART", Some(event)) => {
+                event.start = parse_ical_datetime(value);
+                event.all_day =
+                    params.to_ascii_uppercase().contains("VALUE=DATE") || value.len() == 8;
+            }
+            ("DTEND", Some(event)) => event.end = parse_ical_datetime(value),
+            _ => {}
+        }
+    }
+
+    events
+}
+
+/// Lowercase alphanumerics only, so "Summer Event" matches `summer_event.png`.
+fn normalize_icon_key(name: &str) -> String {
+    name.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|char| char.to_ascii_lowercase())
+        .collect()
+}
+
+/// Finds the icon whose file stem matches the event title.
+fn find_event_icon(config: &Config, summary: &str) -> Option<PathBuf> {
+    let key = normalize_icon_key(summary);
+    [&config.server_icons_unused, &config.server_icons_used]
+        .into_iter()
+        .filter_map(|directory| load_icon_paths(directory, config.follow_symlinks).ok())
+        .flatten()
+        .find(|path| {
+            path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .is_some_and(|stem| normalize_icon_key(stem) == key)
+        })
+}
+
+/// The filename of the icon applied for the currently active calendar event.
+struct ActiveCalendarEvent;
+
+impl TypeMapKey for ActiveCalendarEvent {
+    type Value = Option<String>;
+}
+
+async fn active_calendar_event(ctx: &Context) -> Option<String> {
+    ctx.data
+        .read()
+        .await
+        .get::<ActiveCalendarEvent>()
+        .cloned()
+        .flatten()
+}
+
+/// HTTP client for outgoing downloads, failing requests that take longer than
+/// `http_timeout_seconds`.
+fn http_client(config: &Config) -> Result<reqwest::Client> {
+    Ok(reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.http_timeout_seconds.max(1)))
+        .build()?)
+}
+
+/// Whether the error is an HTTP request that timed out.
+fn is_timeout(error: &Report) -> bool {
+    error
+        .downcast_ref::<reqwest::Error>()
+        .is_some_and(reqwest::Error::is_timeout)
+}
+
+async fn fetch_calendar_events(config: &Config, url: &str) -> Result<Vec<CalendarEvent>> {
+    let text = http_client(config)?
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+
+    Ok(parse_ical_events(&text))
+}
+
+/// The icon theme last read from `icon_flag_url`, restricting the rotation like a weekday theme.
+struct FlagTheme;
+
+impl TypeMapKey for FlagTheme {
+    type Value = Option<String>;
+}
+
+async fn flag_theme(ctx: &Context) -> Option<String> {
+    ctx.data.read().await.get::<FlagTheme>().cloned().flatten()
+}
+
+/// Polls `icon_flag_url` for the active theme, using `icon_flag_default` while it fails.
+async fn run_icon_flag_loop(ctx: Context) {
+    loop {
+        let Some(config) = get_config(&ctx).await else {
+            return;
+        };
+
+        let Some(url) = config.icon_flag_url.clone().filter(|url| !url.is_empty()) else {
+            return;
+        };
+
+        let theme = match fetch_flag_theme(&config, &url).await {
+            Ok(theme) => theme,
+            Err(error) => {
+                if is_timeout(&error) {
+                    warn!(
+                        "Fetching icon flag '{url}' timed out after {} seconds",
+                        config.http_timeout_seconds
+                    );
+                } else {
+                    error!("Error fetching icon flag '{url}': {error}");
+                }
+
+                config.icon_flag_default.clone()
+            }
+        };
+
+        let mut data = ctx.data.write().await;
+        let current = data.entry::<FlagTheme>().or_default();
+        if *current != theme {
+            if let Some(theme) = &theme {
+                info!("The icon flag switched the theme to '{theme}'");
+            } else {
+                info!("The icon flag cleared the theme");
+            }
+
+            current.clone_from(&theme);
+        }
+        drop(data);
+
+        let refresh = config.icon_flag_refresh_minutes.max(1);
+        sleep(Duration::from_secs(refresh.saturating_mul(60))).await;
+    }
+}
+
+/// Reads the theme from the flag response, a JSON string or an object with `icon_flag_field`.
+async fn fetch_flag_theme(config: &Config, url: &str) -> Result<Option<String>> {
+    let text = http_client(config)?
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+
+    let value = match serde_json::from_str(&text)? {
+        serde_json::Value::Object(mut object) => {
+            object.remove(&config.icon_flag_field).unwrap_or_default()
+        }
+        value => value,
+    };
+
+    match value {
+        serde_json::Value::String(theme) => Ok(Some(theme).filter(|theme| !theme.is_empty())),
+        serde_json::Value::Null => Ok(None),
+        other => bail!("expected a theme name, got {other}"),
+    }
+}
+
+/// Periodically reads `icon_calendar_url` and applies the icon matching the active event, going
+/// back to the normal rotation once the event ends.
+async fn run_calendar_loop(ctx: Context) {
+    loop {
+        let Some(config) = get_config(&ctx).await else {
+            return;
+        };
+
+        let Some(url) = config
+            .icon_calendar_url
+            .clone()
+            .filter(|url| !url.is_empty())
+        else {
+            return;
+        };
+
+        match fetch_calendar_events(&config, &url).await {
+            Ok(events) => {
+                if let Err(error) = sync_calendar_icon(&ctx, &config, &events).await {
+                    error!("Error applying calendar event icon: {error}");
+                }
+            }
+            Err(error) if is_timeout(&error) => warn!(
+                "Fetching icon calendar '{url}' timed out after {} seconds",
+                config.http_timeout_seconds
+            ),
+            Err(error) => error!("Error fetching icon calendar '{url}': {error}"),
+        }
+
+        let refresh = config.icon_calendar_refresh_minutes.max(1);
+        sleep(Duration::from_secs(refresh.saturating_mul(60))).await;
+    }
+}
+
+async fn sync_calendar_icon(
+    ctx: &Context,
+    config: &Config,
+    events: &[CalendarEvent],
+) -> Result<()> {
+    let now = Utc::now();
+    let active = events
+        .iter()
+        .filter(|event| event.start <= now && now < event.end)
+        .find_map(|event| find_event_icon(config, &event.summary).map(|path| (event, path)));
+
+    let current = active_calendar_event(ctx).await;
+    let hold = if maintenance_active(ctx).await {
+        Some(String::from("Maintenance mode is active"))
+    } else {
+        pinned_icon(ctx)
+            .await
+            .map(|pinned| format!("Server icon '{pinned}' is pinned"))
+    };
+    match active {
+        Some((event, path)) => {
+            let icon_name = icon_filename(&path)?;
+            if current.as_deref() == Some(icon_name.as_str()) {
+                return Ok(());
+            }
+
+            if let Some(reason) = hold {
+                info!(
+                    "{reason}, not applying the icon for calendar event '{}'",
+                    event.summary
+                );
+                return Ok(());
+            }
+
+            info!(
+                "Calendar event '{}' started, applying '{icon_name}'",
+                event.summary
+            );
+            let icon = IconCandidate::load(path);
+            let attachment = icon_attachment(&icon.path, config.convert_icons).await?;
+            let lock = icon_lock(ctx).await;
+            let guard = lock.lock().await;
+            apply_server_icon(ctx, config, &icon, &attachment, IconSource::Event).await?;
+            drop(guard);
+            ctx.data
+                .write()
+                .await
+                .insert::<ActiveCalendarEvent>(Some(icon_name));
+        }
+        None if current.is_some() => {
+            ctx.data.write().await.insert::<ActiveCalendarEvent>(None);
+            if let Some(reason) = hold {
+                info!("Calendar event ended, keeping the current icon: {reason}");
+                return Ok(());
+            }
+
+            info!("Calendar event ended, resuming the normal server icon rotation");
+            randomize_server_icon(ctx).await?;
+        }
+        None => {}
+    }
+
+    Ok(())
+}
+
+/// A log file that rotates to `<path>.1` through `<path>.<max_files>` once it grows past `max_bytes`.
+struct RotatingFile {
+    path:      PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file:      fs::File,
+    size:      u64,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = fs::File::options().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_bytes,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        if self.max_files > 0 {
+            for index in (1..self.max_files).rev() {
+                let source = self.rotated_path(index);
+                if source.exists() {
+                    fs::rename(&source, self.rotated_path(index + 1))?;
+                }
+            }
+
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        self.file = fs::File::options()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+
+        Ok(())
+    }
+}
+
+impl io::Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// The reloadable log filter `/loglevel` changes, with the directives it started with.
+struct LogFilter {
+    handle:   reload::Handle<EnvFilter, Registry>,
+    default:  String,
+    /// Bumped on every change, so a pending reset only reverts the change that scheduled it.
+    revision: u64,
+}
+
+impl TypeMapKey for LogFilter {
+    type Value = Self;
+}
+
+fn init_logging(config: &Config) -> Result<LogFilter> {
+    let default = std::env::var(EnvFilter::DEFAULT_ENV)
+        .ok()
+        .filter(|directives| EnvFilter::try_new(directives).is_ok())
+        .unwrap_or_else(|| String::from("warn,the_devs_bot=info"));
+    let (filter, handle) = reload::Layer::new(EnvFilter::new(&default));
+
+    let file_layer = match &config.log_file {
+        Some(path) if !path.as_os_str().is_empty() => {
+            let file =
+                RotatingFile::open(path, config.log_file_max_bytes, config.log_file_max_files)
+                    .map_err(|error| {
+                        eyre!("Failed to open log file '{}': {error}", path.display())
+                    })?;
+
+            Some(
+                fmt::layer()
+                    .with_ansi(false)
+                    .with_writer(std::sync::Mutex::new(file)),
+            )
+        }
+        _ => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer())
+        .with(file_layer)
+        .try_init()?;
+
+    Ok(LogFilter {
+        handle,
+        default,
+        revision: 0,
+    })
+}
+
+async fn get_config(ctx: &Context) -> Option<Config> {
+    ctx.data.read().await.get::<Config>().cloned()
+}
+
+/// The random number generator shared by icon selection and delay computation.
+struct IconRng;
+
+impl TypeMapKey for IconRng {
+    type Value = StdRng;
+}
+
+/// Seeds from `rng_seed` when set so runs are reproducible, otherwise from the OS.
+fn new_icon_rng(config: &Config) -> StdRng {
+    config
+        .rng_seed
+        .map_or_else(StdRng::from_os_rng, StdRng::seed_from_u64)
+}
+
+async fn with_rng<T>(ctx: &Context, f: impl FnOnce(&mut StdRng) -> T) -> T {
+    let mut data = ctx.data.write().await;
+    let result = match data.get_mut::<IconRng>() {
+        Some(rng) => f(rng),
+        None => f(&mut StdRng::from_os_rng()),
+    };
+    drop(data);
+
+    result
+}
+
+/// Checks the token against `GET /users/@me` so a bad token fails fast instead of in the gateway.
+async fn validate_token(token: &str) -> Result<CurrentUser> {
+    match Http::new(token).get_current_user().await {
+        Ok(user) => Ok(user),
+        Err(SerenityError::Http(HttpError::UnsuccessfulRequest(response)))
+            if response.status_code == StatusCode::UNAUTHORIZED =>
+        {
+            bail!("Invalid Discord token: Discord rejected it with 401 Unauthorized, check the token in the config file")
+        }
+        Err(error) => bail!("Failed to validate the Discord token: {error}"),
+    }
+}
+
+fn gateway_intents(config: &Config) -> Result<GatewayIntents> {
+    let mut intents = GatewayIntents::non_privileged();
+    for name in &config.additional_intents {
+        let intent = GatewayIntents::from_name(&name.to_ascii_uppercase())
+            .ok_or_else(|| eyre!("Unknown gateway intent '{name}' in additional_intents"))?;
+        intents |= intent;
+    }
+
+    Ok(intents)
+}
+
+/// Privileged intents required by the features enabled in the config, paired with the feature
+/// that needs them; the non-privileged ones are always requested.
+fn required_intents(config: &Config) -> Vec<(GatewayIntents, &'static str)> {
+    let mut required = Vec::new();
+    if config.text_command_prefix.is_some() {
+        required.push((GatewayIntents::MESSAGE_CONTENT, "text commands"));
+    }
+
+    if !config.member_milestones.is_empty() {
+        required.push((GatewayIntents::GUILD_MEMBERS, "member milestones"));
+    }
+
+    if config.bot_nickname.is_some() {
+        required.push((
+            GatewayIntents::GUILD_MEMBERS,
+            "bot nickname drift correction",
+        ));
+    }
+
+    required
+}
+
+fn warn_missing_intents(config: &Config, intents: GatewayIntents) {
+    for (intent, feature) in required_intents(config) {
+        if !intents.contains(intent) {
+            let names = intent
+                .iter_names()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>();
+            warn!(
+                "{feature} is configured but the {} intent is not enabled; its events will never arrive",
+                names.join(" | ")
+            );
+        }
+    }
+}
+
+fn is_supported_icon(path: &Path) -> bool {
+    let Some(extension) = path.extension().and_then(|ext| ext.to_str()) else {
+        return false;
+    };
+
+    matches!(
+        extension.to_ascii_lowercase().as_str(),
+        "png" | "jpg" | "jpeg" | "gif" | "webp" | "avif"
+    )
+}
+
+/// Whether the icon must be converted before Discord accepts it, regardless of `convert_icons`.
+fn requires_conversion(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("avif"))
+}
+
+/// Whether the icon is converted when `convert_icons` is enabled.
+fn supports_conversion(path: &Path) -> bool {
+    requires_conversion(path)
+        || path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("webp"))
+}
+
+/// Whether the icon moves, i.e. is a GIF or a WebP with the animation flag set in its `VP8X`
+/// header; Discord needs the `ANIMATED_ICON` feature for those.
+fn is_animated_icon(path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .and_then(OsStr::to_str)
+        .map(str::to_ascii_lowercase);
+
+    match extension.as_deref() {
+        Some("gif") => true,
+        Some("webp") => {
+            let mut header = [0; 21];
+            fs::File::open(path)
+                .and_then(|mut file| file.read_exact(&mut header))
+                .is_ok_and(|()| {
+                    &header[0..4] == b"RIFF"
+                        && &header[8..16] == b"WEBPVP8X"
+                        && header[20] & 0x02 != 0
+                })
+        }
+        _ => false,
+    }
+}
+
+/// Converts an AVIF/WebP icon to GIF (animated) or PNG (still), returning the bytes and new filename.
+#[cfg(feature = "convert")]
+fn convert_icon(path: &Path) -> Result<(Vec<u8>, String)> {
+    use std::io::{BufReader, Cursor};
+
+    use image::{
+        codecs::{
+            gif::{GifEncoder, Repeat},
+            webp::WebPDecoder,
+        },
+        AnimationDecoder,
+        ImageFormat,
+    };
+
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| eyre!("Server icon path '{path:?}' is missing a filename"))?;
+
+    if !requires_conversion(path) {
+        let reader = BufReader::new(fs::File::open(path)?);
+        let decoder = WebPDecoder::new(reader)?;
+        if decoder.has_animation() {
+            let mut bytes = Vec::new();
+            {
+                let mut encoder = GifEncoder::new(&mut bytes);
+                encoder.set_repeat(Repeat::Infinite)?;
+                encoder.try_encode_frames(decoder.into_frames())?;
+            }
+
+            return Ok((bytes, format!("{stem}.gif")));
+        }
+    }
+
+    let image = image::open(path)?;
+    let mut bytes = Cursor::new(Vec::new());
+    image.write_to(&mut bytes, ImageFormat::Png)?;
+
+    Ok((bytes.into_inner(), format!("{stem}.png")))
+}
+
+#[cfg(not(feature = "convert"))]
+fn convert_icon(_path: &Path) -> Result<(Vec<u8>, String)> {
+    bail!("image conversion is not compiled in (rebuild with the `convert` feature)")
+}
+
+/// Downscales the icon's first frame to fit in `size` pixels, encoded as PNG.
+#[cfg(feature = "convert")]
+fn thumbnail_icon(path: &Path, size: u32) -> Result<(Vec<u8>, String)> {
+    use std::io::Cursor;
+
+    use image::ImageFormat;
+
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| eyre!("Server icon path '{path:?}' is missing a filename"))?;
+
+    let thumbnail = image::open(path)?.thumbnail(size, size);
+    let mut bytes = Cursor::new(Vec::new());
+    thumbnail.write_to(&mut bytes, ImageFormat::Png)?;
+
+    Ok((bytes.into_inner(), format!("{stem}-thumbnail.png")))
+}
+
+#[cfg(not(feature = "convert"))]
+fn thumbnail_icon(_path: &Path, _size: u32) -> Result<(Vec<u8>, String)> {
+    bail!("thumbnails are not compiled in (rebuild with the `convert` feature)")
+}
+
+/// Width and height of the image, when it can be decoded.
+#[cfg(feature = "convert")]
+fn icon_dimensions(path: &Path) -> Option<(u32, u32)> {
+    image::image_dimensions(path).ok()
+}
+
+#[cfg(not(feature = "convert"))]
+const fn icon_dimensions(_path: &Path) -> Option<(u32, u32)> {
+    None
+}
+
+/// Reasons the file would be skipped or look wrong during rotation; empty when it's fine.
+fn icon_problems(path: &Path, config: &Config) -> Vec<String> {
+    if !is_supported_icon(path) {
+        return vec![String::from("unsupported format")];
+    }
+
+    let mut problems = Vec::new();
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.len() > MAX_ICON_BYTES as u64 => {
+            problems.push(format!(
+                "too big ({} KiB, the limit is 10 MiB)",
+                metadata.len() / 1024
+            ));
+        }
+        Ok(_) => {}
+        Err(error) => problems.push(format!("unreadable ({error})")),
+    }
+
+    if requires_conversion(path) && !config.convert_icons {
+        problems.push(String::from("needs conversion, enable convert_icons"));
+    }
+
+    if let Some((width, height)) = icon_dimensions(path) {
+        if width != height {
+            problems.push(format!("not square ({width}x{height}), it will be cropped"));
+        }
+    }
+
+    if let Some(until) = icon_expiry(path, &load_icon_meta(path)) {
+        if Utc::now().date_naive() > until {
+            problems.push(format!("expired on {until}"));
+        }
+    }
+
+    problems
+}
+
+/// Reads an icon for upload, converting it first when needed and enabled.
+async fn icon_attachment(path: &Path, convert: bool) -> Result<CreateAttachment> {
+    let started = Instant::now();
+    let attachment = read_icon_attachment(path, convert).await;
+    debug!(
+        "Reading server icon '{}' took {:?}",
+        path.display(),
+        started.elapsed()
+    );
+
+    attachment
+}
+
+async fn read_icon_attachment(path: &Path, convert: bool) -> Result<CreateAttachment> {
+    if !convert {
+        if requires_conversion(path) {
+            bail!("this format needs conversion, enable convert_icons");
+        }
+
+        return Ok(CreateAttachment::path(path).await?);
+    }
+
+    /* Discord takes WebP as is, only AVIF is lost without the conversion compiled in */
+    let uploads_raw = !requires_conversion(path) && !cfg!(feature = "convert");
+    if !supports_conversion(path) || uploads_raw {
+        return Ok(CreateAttachment::path(path).await?);
+    }
+
+    let source = path.to_path_buf();
+    let (bytes, filename) = tokio::task::spawn_blocking(move || convert_icon(&source))
+        .await?
+        .map_err(|error| eyre!("conversion failed: {error}"))?;
+
+    info!("Converted server icon '{}' to '{filename}'", path.display());
+    Ok(CreateAttachment::bytes(bytes, filename))
+}
+
+/// Optional per-icon metadata read from a `<icon>.meta` TOML sidecar next to the image.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+struct IconMeta {
+    /// A friendly title shown in announcements instead of the filename.
+    display_name: Option<String>,
+    /// Who made the icon, included when it is applied.
+    credit:       Option<String>,
+    /// A free-form theme or category for the icon.
+    theme:        Option<String>,
+    /// Relative selection weight (defaults to 1).
+    weight:       f64,
+    /// Set to false to exclude the icon from rotation.
+    enabled:      bool,
+    /// The last day (UTC, `"YYYY-MM-DD"`) the icon may be picked, overriding an `__until_` date
+    /// in the filename.
+    until:        Option<String>,
+}
+
+impl Default for IconMeta {
+    fn default() -> Self {
+        Self {
+            display_name: None,
+            credit:       None,
+            theme:        None,
+            weight:       1.0,
+            enabled:      true,
+            until:        None,
+        }
+    }
+}
+
+impl IconMeta {
+    /// The display name when set, the filename otherwise.
+    fn title<'a>(&'a self, filename: &'a str) -> &'a str {
+        self.display_name
+            .as_deref()
+            .filter(|name| !name.trim().is_empty())
+            .unwrap_or(filename)
+    }
+}
+
+/// An icon that can be selected, along with its sidecar metadata.
+#[derive(Clone, Debug)]
+struct IconCandidate {
+    path: PathBuf,
+    meta: IconMeta,
+}
+
+impl IconCandidate {
+    fn load(path: PathBuf) -> Self {
+        let meta = load_icon_meta(&path);
+        Self { path, meta }
+    }
+
+    const fn weight(&self) -> f64 {
+        if self.meta.weight.is_finite() {
+            self.meta.weight.max(0.0)
+        } else {
+            0.0
+        }
+    }
+
+    /// Whether the icon's last day has passed, see [`icon_expiry`].
+    fn is_expired(&self, today: NaiveDate) -> bool {
+        icon_expiry(&self.path, &self.meta).is_some_and(|until| today > until)
+    }
+}
+
+/// The last day an icon may be picked, from the sidecar's `until` or a filename ending in
+/// `__until_YYYY-MM-DD` (before the extension); unparsable dates never expire.
+fn icon_expiry(path: &Path, meta: &IconMeta) -> Option<NaiveDate> {
+    let stem = path.file_stem().and_then(OsStr::to_str).unwrap_or_default();
+    let date = meta
+        .until
+        .as_deref()
+        .or_else(|| stem.rsplit_once("__until_").map(|(_, date)| date))?;
+
+    NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d")
+        .inspect_err(|_| {
+            debug!(
+                "Ignoring invalid expiry date '{date}' of '{}'",
+                path.display()
+            );
+        })
+        .ok()
+}
+
+fn icon_meta_path(path: &Path) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_os_string();
+    sidecar.push(".meta");
+    PathBuf::from(sidecar)
+}
+
+/// Reads the icon's sidecar metadata, falling back to the defaults when missing or invalid.
+fn load_icon_meta(path: &Path) -> IconMeta {
+    let sidecar = icon_meta_path(path);
+    let Ok(text) = fs::read_to_string(&sidecar) else {
+        return IconMeta::default();
+    };
+
+    toml::from_str(&text).unwrap_or_else(|error| {
+        warn!(
+            "Ignoring invalid icon metadata '{}': {error}",
+            sidecar.display()
+        );
+        IconMeta::default()
+    })
+}
+
+fn load_icon_paths(directory: &Path, follow_symlinks: bool) -> Result<Vec<PathBuf>> {
+    if directory.as_os_str().is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let metadata = match fs::metadata(directory) {
+        Ok(meta) => meta,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            fs::create_dir_all(directory)?;
+            return Ok(Vec::new());
+        }
+        Err(error) => bail!(
+            "Failed to access server icon path '{}': {error}",
+            directory.display()
+        ),
+    };
+
+    if !metadata.is_dir() {
+        bail!(
+            "Server icon path '{}' is not a directory",
+            directory.display()
+        );
+    }
+
+    let read_dir = fs::read_dir(directory)
+        .map_err(|error| eyre!("Failed to read '{directory:?}': {error}"))?;
+
+    let mut paths = Vec::new();
+    for entry in read_dir {
+        let path = entry?.path();
+        if !path.is_file() || !is_supported_icon(&path) {
+            continue;
+        }
+
+        if !follow_symlinks && path.is_symlink() {
+            info!(
+                "Skipping '{}', it is a symlink and follow_symlinks is disabled",
+                path.display()
+            );
+            continue;
+        }
+
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+fn icon_filename(path: &Path) -> Result<String> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(std::string::ToString::to_string)
+        .ok_or_else(|| eyre!("Server icon path '{path:?}' is missing a filename"))
+}
+
+/// Whether the icon was modified within `max_age_days`, treating unknown modification times as recent.
+fn is_within_max_age(path: &Path, max_age_days: Option<u64>, now: SystemTime) -> bool {
+    let Some(max_age_days) = max_age_days else {
+        return true;
+    };
+
+    let Ok(modified) = fs::metadata(path).and_then(|metadata| metadata.modified()) else {
+        return true;
+    };
+
+    let max_age = Duration::from_secs(max_age_days.saturating_mul(86_400));
+    now.duration_since(modified)
+        .map_or(true, |age| age <= max_age)
+}
+
+/// What adjusts the icon weights beyond their metadata, by filename.
+#[derive(Default)]
+struct IconWeights {
+    last_shown: BTreeMap<String, Timestamp>,
+    boosts:     HashMap<String, f64>,
+}
+
+/// Picks the next server icon from the candidates, excluding files outside the age window and
+/// favouring icons by their metadata weight, active boosts and, with `recency_weight_days`, by
+/// how long ago they were last shown.
+fn select_icon<R: Rng + ?Sized>(
+    candidates: &[IconCandidate],
+    config: &Config,
+    weights: &IconWeights,
+    now: SystemTime,
+    rng: &mut R,
+) -> Option<IconCandidate> {
+    let eligible = candidates
+        .iter()
+        .filter(|candidate| {
+            let enabled = candidate.meta.enabled;
+            if !enabled {
+                debug!(
+                    "Excluding '{}': disabled in its sidecar",
+                    candidate.path.display()
+                );
+            }
+
+            enabled
+        })
+        .filter(|candidate| {
+            let expired = candidate.is_expired(DateTime::<Utc>::from(now).date_naive());
+            if expired {
+                debug!(
+                    "Excluding '{}': past its expiry date",
+                    candidate.path.display()
+                );
+            }
+
+            !expired
+        })
+        .filter(|candidate| {
+            let recent = is_within_max_age(&candidate.path, config.icon_max_age_days, now);
+            if !recent {
+                debug!(
+                    "Excluding '{}': older than the age limit",
+                    candidate.path.display()
+                );
+            }
+
+            recent
+        })
+        .collect::<Vec<_>>();
+
+    let now_secs = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| {
+            i64::try_from(elapsed.as_secs()).unwrap_or(i64::MAX)
+        });
+
+    let weight_of = |candidate: &IconCandidate| {
+        let name = icon_filename(&candidate.path).unwrap_or_default();
+        let shown = weights.last_shown.get(&name);
+        let boost = weights.boosts.get(&name).copied().unwrap_or(1.0);
+        candidate.weight() * boost * recency_factor(shown, now_secs, config.recency_weight_days)
+    };
+
+    if tracing::enabled!(tracing::Level::DEBUG) {
+        for candidate in &eligible {
+            debug!(
+                "Candidate '{}' has weight {:.3}",
+                candidate.path.display(),
+                weight_of(candidate)
+            );
+        }
+    }
+
+    eligible
+        .choose_weighted(rng, |candidate| weight_of(candidate))
+        .ok()
+        .or_else(|| eligible.choose(rng))
+        .map(|candidate| (*candidate).clone())
+}
+
+/// Scales the weight from 1% right after an icon was shown up to 100% once `window_days` have
+/// passed; icons that were never shown get the full weight.
+fn recency_factor(last_shown: Option<&Timestamp>, now_secs: i64, window_days: Option<u64>) -> f64 {
+    let (Some(last_shown), Some(window_days)) = (last_shown, window_days.filter(|days| *days > 0))
+    else {
+        return 1.0;
+    };
+
+    /* Seconds fit comfortably in an f64 */
+    #[allow(clippy::cast_precision_loss)]
+    let ratio =
+        now_secs.saturating_sub(last_shown.unix_timestamp()) as f64 / (window_days * 86_400) as f64;
+
+    ratio.clamp(0.01, 1.0)
+}
+
+/// Forgets when icons were shown once that is older than `window_days`, where they are back at
+/// the full weight anyway, so the state doesn't grow with every icon ever applied.
+fn prune_last_shown(
+    last_shown: &mut BTreeMap<String, Timestamp>,
+    now_secs: i64,
+    window_days: Option<u64>,
+) {
+    let Some(window_days) = window_days.filter(|days| *days > 0) else {
+        return;
+    };
+
+    let window = i64::try_from(window_days.saturating_mul(86_400)).unwrap_or(i64::MAX);
+    let cutoff = now_secs.saturating_sub(window);
+    last_shown.retain(|_, shown| shown.unix_timestamp() >= cutoff);
+}
+
+/// Temporary weight multipliers from `/icon boost`, by filename, with when they expire.
+struct IconBoosts;
+
+impl TypeMapKey for IconBoosts {
+    type Value = HashMap<String, (f64, Instant)>;
+}
+
+/// The last-shown times and the boosts that haven't expired yet, pruning the expired ones.
+async fn icon_weights(ctx: &Context) -> IconWeights {
+    let mut data = ctx.data.write().await;
+    let last_shown = data
+        .get::<State>()
+        .map(|state| state.last_shown.clone())
+        .unwrap_or_default();
+
+    /* Expired boosts are dropped here, so the map only holds the ones still running */
+    let now = Instant::now();
+    let boosts = data
+        .get_mut::<IconBoosts>()
+        .map(|boosts| {
+            boosts.retain(|_, (_, expires_at)| *expires_at > now);
+            boosts
+                .iter()
+                .map(|(name, (multiplier, _))| (name.clone(), *multiplier))
+                .collect()
+        })
+        .unwrap_or_default();
+    drop(data);
+
+    IconWeights { last_shown, boosts }
+}
+
+/// Filenames of every icon in the unused and used directories, unused first.
+fn icon_names(config: &Config) -> Vec<String> {
+    [&config.server_icons_unused, &config.server_icons_used]
+        .into_iter()
+        .filter_map(|directory| load_icon_paths(directory, config.follow_symlinks).ok())
+        .flatten()
+        .filter_map(|path| icon_filename(&path).ok())
+        .collect()
+}
+
+/// Looks up an icon by filename in the unused and used directories.
+fn find_icon(config: &Config, filename: &str) -> Option<PathBuf> {
+    [&config.server_icons_unused, &config.server_icons_used]
+        .into_iter()
+        .filter_map(|directory| load_icon_paths(directory, config.follow_symlinks).ok())
+        .flatten()
+        .find(|path| icon_filename(path).is_ok_and(|name| name == filename))
+}
+
+/// Whether the file and directory live on different filesystems, where a rename cannot work.
+#[cfg(unix)]
+fn is_cross_device(source: &Path, target_dir: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (fs::metadata(source), fs::metadata(target_dir)) {
+        (Ok(source), Ok(target)) => source.dev() != target.dev(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_cross_device(_source: &Path, _target_dir: &Path) -> bool {
+    false
+}
+
+/// Recreates the link at the destination pointing at the resolved target, so relative links keep
+/// working and the shared file itself is never moved.
+#[cfg(unix)]
+fn move_symlink(source: &Path, destination: &Path) -> Result<()> {
+    let target = fs::canonicalize(source)
+        .map_err(|error| eyre!("Failed to resolve symlink '{}': {error}", source.display()))?;
+
+    if destination.exists() || destination.is_symlink() {
+        fs::remove_file(destination)?;
+    }
+
+    std::os::unix::fs::symlink(&target, destination)?;
+    fs::remove_file(source)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn move_symlink(source: &Path, destination: &Path) -> Result<()> {
+    move_file(source, destination)
+}
+
+fn copy_and_remove(source: &Path, destination: &Path) -> io::Result<()> {
+    fs::copy(source, destination)?;
+    fs::remove_file(source)
+}
+
+fn move_file(source: &Path, destination: &Path) -> Result<()> {
+    let cross_device = destination
+        .parent()
+        .is_some_and(|target_dir| is_cross_device(source, target_dir));
+
+    move_file_across(source, destination, cross_device)
+}
+
+/// Renames the file, or copies and removes it when it crosses devices or the rename fails.
+fn move_file_across(source: &Path, destination: &Path, cross_device: bool) -> Result<()> {
+    if destination.exists() {
+        fs::remove_file(destination)?;
+    }
+
+    if !cross_device {
+        match fs::rename(source, destination) {
+            Ok(()) => return Ok(()),
+            Err(error) if error.kind() == io::ErrorKind::CrossesDevices => {}
+            Err(rename_error) => {
+                return copy_and_remove(source, destination).map_err(|copy_error| {
+                    eyre!(
+                        "Failed to move icon from '{}' to '{}': rename error: {rename_error}; copy error: {copy_error}",
+                        source.display(),
+                        destination.display()
+                    )
+                });
+            }
+        }
+    }
+
+    copy_and_remove(source, destination).map_err(|error| {
+        eyre!(
+            "Failed to copy icon from '{}' to '{}': {error}",
+            source.display(),
+            destination.display()
+        )
+    })
+}
+
+/// Moves an icon, along with its metadata sidecar if present, into `target_dir`.
+fn move_icon_file(source: &Path, target_dir: &Path) -> Result<PathBuf> {
+    let filename = icon_filename(source)?;
+    let destination = target_dir.join(&filename);
+    if destination == source {
+        return Ok(destination);
+    }
+
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if source.is_symlink() {
+        move_symlink(source, &destination)?;
+    } else {
+        move_file(source, &destination)?;
+    }
+
+    let sidecar = icon_meta_path(source);
+    if sidecar.is_file() {
+        move_file(&sidecar, &icon_meta_path(&destination))?;
+    }
+
+    Ok(destination)
+}
+
+/// Moves used icons back to the unused directory, only the `batch_size` applied longest ago
+/// (by `last_shown`, icons without an entry first) when set, so the whole set doesn't return at
+/// once.
+fn recycle_used_icons(
+    unused_dir: &Path,
+    used_dir: &Path,
+    batch_size: Option<usize>,
+    follow_symlinks: bool,
+    last_shown: &BTreeMap<String, Timestamp>,
+) -> Result<Vec<PathBuf>> {
+    let mut used_paths = load_icon_paths(used_dir, follow_symlinks)?;
+    if used_paths.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    /* Moves keep the modification time, so it says nothing about when an icon was applied */
+    if let Some(batch_size) = batch_size {
+        used_paths.sort_by_cached_key(|path| {
+            let name = icon_filename(path).unwrap_or_default();
+            let shown = last_shown.get(&name).map(Timestamp::unix_timestamp);
+            (shown, name)
+        });
+        used_paths.truncate(batch_size.max(1));
+    }
+
+    let mut moved = Vec::new();
+    for path in used_paths {
+        let destination = move_icon_file(&path, unused_dir)?;
+        moved.push(destination);
+    }
+
+    Ok(moved)
+}
+
+fn icon_delay<R: Rng + ?Sized>(
+    min: Duration,
+    max: Duration,
+    rng: &mut R,
+) -> Result<Option<Duration>> {
+    if max.is_zero() {
+        return Ok(None);
+    }
+
+    if min > max {
+        bail!(
+            "the minimum delay ({}) cannot be greater than the maximum delay ({})",
+            humantime::format_duration(min),
+            humantime::format_duration(max)
+        );
+    }
+
+    let seconds = if min == max {
+        min.as_secs()
+    } else {
+        rng.random_range(min.as_secs()..=max.as_secs())
+    };
+
+    Ok((seconds > 0).then(|| Duration::from_secs(seconds)))
+}
+
+/// The delay range for logs and replies, e.g. `30m-2h`.
+fn describe_delay_range((min, max): (Duration, Duration)) -> String {
+    format!(
+        "{}-{}",
+        humantime::format_duration(min),
+        humantime::format_duration(max)
+    )
+}
+
+/// Wakes the rotation loop so it draws a new delay, e.g. after the delay range changed.
+struct IconReschedule;
+
+impl TypeMapKey for IconReschedule {
+    type Value = Arc<Notify>;
+}
+
+async fn icon_reschedule(ctx: &Context) -> Arc<Notify> {
+    ctx.data
+        .write()
+        .await
+        .entry::<IconReschedule>()
+        .or_default()
+        .clone()
+}
+
+async fn next_icon_delay(ctx: &Context) -> Result<Option<Duration>> {
+    let Some(config) = get_config(ctx).await else {
+        return Ok(None);
+    };
+
+    let (min, max) = config.icon_delay_range()?;
+    with_rng(ctx, |rng| icon_delay(min, max, rng)).await
+}
+
+/// Applies `startup_icon_behavior`, returning what is left of the delay when resuming it.
+async fn startup_icon_rotation(ctx: &Context) -> Option<Duration> {
+    let config = get_config(ctx).await?;
+    if let Some(reason) = rotation_hold(ctx).await {
+        info!("{reason}, skipping the startup rotation");
+        return None;
+    }
+
+    if icon_breaker_paused(ctx).await {
+        warn!(
+            "Server icon rotation is paused by the circuit breaker, skipping the startup rotation"
+        );
+        return None;
+    }
+
+    let next_change = ctx
+        .data
+        .read()
+        .await
+        .get::<State>()
+        .and_then(|state| state.next_icon_change);
+    let remaining = next_change
+        .map(|next_change| next_change.unix_timestamp() - Timestamp::now().unix_timestamp())
+        .and_then(|remaining| u64::try_from(remaining).ok())
+        .filter(|remaining| *remaining > 0);
+
+    match (config.startup_icon_behavior, remaining) {
+        (StartupIconBehavior::Skip, _) => {
+            info!("Keeping the current server icon on startup");
+            None
+        }
+        (StartupIconBehavior::Resume, Some(remaining)) => {
+            info!("Resuming the server icon delay, {remaining} seconds left");
+            Some(Duration::from_secs(remaining))
+        }
+        (StartupIconBehavior::Rotate | StartupIconBehavior::Resume, _) => {
+            if let Err(error) = rotate_server_icon(ctx).await {
+                error!("Error randomizing server icon: {error}");
+            }
+
+            None
+        }
+    }
+}
+
+/// Logs a guild that isn't the configured one, then tells its owner (only right after being
+/// added, not on every restart) and leaves it when configured to.
+async fn handle_unconfigured_guild(ctx: &Context, config: &Config, guild: &Guild, is_new: bool) {
+    warn!(
+        "Joined guild '{}' ({}), which is not the configured guild {}",
+        guild.name, guild.id, config.guild
+    );
+
+    if is_new && config.notify_unconfigured_guild_owner {
+        let content = format!(
+            "Thanks for adding me to **{}**! This bot only serves the server it is configured \
+             for, so ask its operator to set it up for yours{}",
+            guild.name,
+            if config.leave_unconfigured_guilds {
+                ", I will leave in the meantime"
+            } else {
+                ""
+            }
+        );
+
+        let result = match guild.owner_id.create_dm_channel(&ctx.http).await {
+            Ok(channel) => channel
+                .send_message(&ctx.http, CreateMessage::new().content(content))
+                .await
+                .map(|_| ()),
+            Err(error) => Err(error),
+        };
+
+        if let Err(error) = result {
+            error!("Error messaging the owner of guild {}: {error}", guild.id);
+        }
+    }
+
+    if config.leave_unconfigured_guilds {
+        match guild.id.leave(&ctx.http).await {
+            Ok(()) => info!("Left unconfigured guild '{}' ({})", guild.name, guild.id),
+            Err(error) => error!("Error leaving guild {}: {error}", guild.id),
+        }
+    }
+}
+
+/// Serves `GET /health` and, with `http_api_token` set, `POST /rotate` which applies a random
+/// icon under the usual rotation lock and replies with its filename. Rotations are refused while
+/// `rotation_hold` applies or the icon circuit breaker is open.
+async fn run_http_api(ctx: Context) {
+    let Some(address) = get_config(&ctx)
+        .await
+        .and_then(|config| config.http_api_address)
+    else {
+        return;
+    };
+
+    let listener = match TcpListener::bind(&address).await {
+        Ok(listener) => listener,
+        Err(error) => {
+            error!("Error binding the HTTP API to '{address}': {error}");
+            return;
+        }
+    };
+
+    info!("HTTP API listening on '{address}'");
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let ctx = ctx.clone();
+                tokio::spawn(async move {
+                    if let Err(error) = handle_http_request(&ctx, stream).await {
+                        debug!("Error handling HTTP request: {error}");
+                    }
+                });
+            }
+            Err(error) => error!("Error accepting HTTP connection: {error}"),
+        }
+    }
+}
+
+/// Reads a single request and answers it with a plain text body, closing the connection after.
+async fn handle_http_request(ctx: &Context, mut stream: TcpStream) -> Result<()> {
+    let (reader, mut writer) = stream.split();
+    let mut reader = BufReader::new(reader.take(16 * 1024));
+
+    let head = timeout(Duration::from_secs(10), async {
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).await?;
+
+        let mut authorization = None;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+                break;
+            }
+
+            if let Some((name, value)) = line.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("authorization") {
+                    authorization = Some(value.trim().to_string());
+                }
+            }
+        }
+
+        Ok::<_, io::Error>((request_line, authorization))
+    })
+    .await??;
+
+    let (request_line, authorization) = head;
+    let mut parts = request_line.split_whitespace();
+    let route = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+    );
+
+    let (status, body) = match route {
+        ("GET", "/health") => ("200 OK", String::from("ok")),
+        ("POST", "/rotate") => http_rotate(ctx, authorization.as_deref()).await,
+        (_, "/health" | "/rotate") => {
+            ("405 Method Not Allowed", String::from("method not allowed"))
+        }
+        _ => ("404 Not Found", String::from("not found")),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    writer.write_all(response.as_bytes()).await?;
+    writer.shutdown().await?;
+
+    Ok(())
+}
+
+async fn http_rotate(ctx: &Context, authorization: Option<&str>) -> (&'static str, String) {
+    let token = get_config(ctx)
+        .await
+        .and_then(|config| config.http_api_token)
+        .filter(|token| !token.is_empty());
+    let Some(token) = token else {
+        return ("404 Not Found", String::from("not found"));
+    };
+
+    let bearer = authorization.and_then(|value| value.strip_prefix("Bearer "));
+    if !bearer.is_some_and(|bearer| constant_time_eq(bearer.as_bytes(), token.as_bytes())) {
+        warn!("Rejected an HTTP icon rotation with a missing or wrong token");
+        return ("401 Unauthorized", String::from("unauthorized"));
+    }
+
+    if let Some(reason) = rotation_hold(ctx).await {
+        info!("{reason}, refusing an HTTP icon rotation");
+        return ("409 Conflict", format!("{reason}, not rotating the icon"));
+    }
+
+    if let Some(remaining) = icon_breaker_remaining(ctx).await {
+        let content = format!(
+            "icon changes are paused after repeated failures, retry in {} seconds",
+            remaining.as_secs() + 1
+        );
+        return ("503 Service Unavailable", content);
+    }
+
+    match randomize_server_icon(ctx).await {
+        Ok(Some(icon_name)) => {
+            info!("Rotated the server icon to '{icon_name}' through the HTTP API");
+            ("200 OK", icon_name)
+        }
+        Ok(None) => (
+            "409 Conflict",
+            String::from("no icon could be applied, check the icon directories"),
+        ),
+        Err(error) => {
+            error!("Error randomizing server icon: {error}");
+            ("500 Internal Server Error", error.to_string())
+        }
+    }
+}
+
+/// Compares two byte strings in time that depends only on their lengths, not their contents.
+fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    left.len() == right.len()
+        && left
+            .iter()
+            .zip(right)
+            .fold(0, |difference, (left, right)| difference | (left ^ right))
+            == 0
+}
+
+/// Set once the missing Move Members permission was reported, so it's only posted once per run.
+struct MoveMembersWarned;
+
+impl TypeMapKey for MoveMembersWarned {
+    type Value = bool;
+}
+
+/// Logs a failed move, telling the staff once when it's down to the missing Move Members
+/// permission instead of repeating the error on every stream.
+async fn report_move_failure(ctx: &Context, config: &Config, error: &SerenityError) {
+    let forbidden = matches!(
+        error,
+        SerenityError::Http(HttpError::UnsuccessfulRequest(response))
+            if response.status_code == StatusCode::FORBIDDEN
+    );
+    if !forbidden {
+        error!("Error moving channel: {error}");
+        return;
+    }
+
+    let mut data = ctx.data.write().await;
+    let warned = data.entry::<MoveMembersWarned>().or_default();
+    let first = !*warned;
+    *warned = true;
+    drop(data);
+
+    if first {
+        let content = "Members can't be moved to the video channel: the bot is missing the Move Members permission there";
+        warn!("{content}");
+        notify_staff(ctx, config, content).await;
+    } else {
+        debug!("Error moving channel, Move Members is still missing: {error}");
+    }
+}
+
+/// Which idle presence text is showing, and the presence last sent so unchanged ones are skipped.
+struct PresenceIndex;
+
+impl TypeMapKey for PresenceIndex {
+    type Value = usize;
+}
+
+struct CurrentPresence;
+
+impl TypeMapKey for CurrentPresence {
+    type Value = String;
+}
+
+/// Members in any of the video channels, from the cached voice states.
+fn video_occupancy(ctx: &Context, config: &Config) -> usize {
+    let pairs = voice_pairs(config);
+    ctx.cache.guild(config.guild).map_or(0, |guild| {
+        guild
+            .voice_states
+            .values()
+            .filter(|state| {
+                state
+                    .channel_id
+                    .is_some_and(|channel_id| pairs.iter().any(|pair| pair.video == channel_id))
+            })
+            .count()
+    })
+}
+
+/// Shows the active template while anyone is in a video channel, otherwise the current idle text.
+async fn refresh_presence(ctx: &Context) {
+    let Some(config) = get_config(ctx).await else {
+        return;
+    };
+
+    let watching = video_occupancy(ctx, &config);
+    let activity = match &config.presence_active_template {
+        Some(template) if watching > 0 => {
+            ActivityData::watching(template.replace("{count}", &watching.to_string()))
+        }
+        _ if config.presence_idle_texts.is_empty() => ActivityData::playing("with commands"),
+        _ => {
+            let index = ctx
+                .data
+                .read()
+                .await
+                .get::<PresenceIndex>()
+                .copied()
+                .unwrap_or_default();
+            let text = &config.presence_idle_texts[index % config.presence_idle_texts.len()];
+            ActivityData::playing(text)
+        }
+    };
+
+    let mut data = ctx.data.write().await;
+    let current = data.entry::<CurrentPresence>().or_default();
+    if *current == activity.name {
+        return;
+    }
+
+    current.clone_from(&activity.name);
+    drop(data);
+
+    ctx.set_presence(Some(activity), OnlineStatus::Online);
+}
+
+/// Moves on to the next idle presence text every `presence_rotate_minutes`.
+async fn run_presence_loop(ctx: Context) {
+    loop {
+        let Some(config) = get_config(&ctx).await else {
+            return;
+        };
+
+        if config.presence_idle_texts.len() < 2 {
+            return;
+        }
+
+        let minutes = config.presence_rotate_minutes.max(1);
+        sleep(Duration::from_secs(minutes.saturating_mul(60))).await;
+        *ctx.data.write().await.entry::<PresenceIndex>().or_default() += 1;
+        refresh_presence(&ctx).await;
+    }
+}
+
+/// Rotates the server icon after each random delay, starting with `resume_delay` when set.
+async fn run_icon_rotation_loop(ctx: Context, mut resume_delay: Option<Duration>) {
+    let reschedule = icon_reschedule(&ctx).await;
+    loop {
+        /* Wait out an open breaker first, so the next delay starts once it closes */
+        if let Some(remaining) = icon_breaker_remaining(&ctx).await {
+            warn!("Server icon rotation is paused by the circuit breaker for {remaining:?}");
+            tokio::select! {
+                () = sleep(remaining) => {}
+                () = reschedule.notified() => {}
+            }
+            continue;
+        }
+
+        let next_delay = match resume_delay.take() {
+            Some(delay) => Ok(Some(delay)),
+            None => next_icon_delay(&ctx).await,
+        };
+
+        let Some(delay) = (match next_delay {
+            Ok(delay) => delay,
+            Err(error) => {
+                /* Only a reload or /icon interval can fix the range, so wait for one of them */
+                error!("Error calculating server icon delay: {error}");
+                reschedule.notified().await;
+                continue;
+            }
+        }) else {
+            info!("Server icon delay disabled; pausing icon randomizer loop");
+            reschedule.notified().await;
+            continue;
+        };
+
+        let secs = i64::try_from(delay.as_secs()).unwrap_or(i64::MAX);
+        let next_change =
+            Timestamp::from_unix_timestamp(Timestamp::now().unix_timestamp().saturating_add(secs))
+                .ok();
+        if let Err(error) = update_state(&ctx, |state| state.next_icon_change = next_change).await {
+            error!("Error saving state: {error}");
+        }
+
+        let range = get_config(&ctx)
+            .await
+            .and_then(|config| config.icon_delay_range().ok())
+            .map(describe_delay_range)
+            .unwrap_or_default();
+        info!("Waiting {delay:?} before updating server icon (range {range})");
+
+        tokio::select! {
+            () = sleep(delay) => {}
+            () = reschedule.notified() => {
+                info!("Server icon delay range changed, rescheduling");
+                continue;
+            }
+        }
+
+        if let Some(reason) = rotation_hold(&ctx).await {
+            info!("{reason}, skipping server icon rotation");
+            continue;
+        }
+
+        if icon_breaker_paused(&ctx).await {
+            warn!("Server icon rotation is paused by the circuit breaker, skipping");
+            continue;
+        }
+
+        if let Err(error) = rotate_server_icon(&ctx).await {
+            error!("Error randomizing server icon: {error}");
+        }
+    }
+}
+
+/// Starts the loops that run beside the icon rotation, once per process.
+async fn spawn_background_tasks(ctx: &Context) {
+    if start_background_task(ctx, "calendar").await {
+        tokio::spawn(run_calendar_loop(ctx.clone()));
+    }
+
+    if start_background_task(ctx, "icon-flag").await {
+        tokio::spawn(run_icon_flag_loop(ctx.clone()));
+    }
+
+    if start_background_task(ctx, "icon-repo").await {
+        tokio::spawn(run_icon_repo_loop(ctx.clone()));
+    }
+
+    if start_background_task(ctx, "summary").await {
+        tokio::spawn(run_summary_loop(ctx.clone()));
+    }
+
+    if start_background_task(ctx, "icon-of-the-day").await {
+        tokio::spawn(run_icon_of_the_day_loop(ctx.clone()));
+    }
+
+    if start_background_task(ctx, "maintenance").await {
+        tokio::spawn(run_maintenance_schedule_loop(ctx.clone()));
+    }
+
+    if start_background_task(ctx, "presence").await {
+        tokio::spawn(run_presence_loop(ctx.clone()));
+    }
+
+    if start_background_task(ctx, "http-api").await {
+        tokio::spawn(run_http_api(ctx.clone()));
+    }
+}
+
+struct Events;
+
+#[async_trait]
+impl EventHandler for Events {
+    async fn ready(&self, ctx: Context, data_about_bot: Ready) {
+        info!("Ready: {}", data_about_bot.user.name);
+
+        refresh_presence(&ctx).await;
+
+        enforce_bot_nickname(&ctx, data_about_bot.user.id, None).await;
+
+        enforce_guild_name(&ctx).await;
+
+        if let Some(config) = get_config(&ctx).await {
+            if let Err(error) = check_member_milestone(&ctx, &config, None).await {
+                error!("Error checking member milestones: {error}");
+            }
+        }
+
+        match sync_commands(&ctx).await {
+            Ok(commands) => info!("Successfully registered {} commands", commands.len()),
+            Err(error) => error!("Error creating commands: {error}"),
+        }
+
+        if start_background_task(&ctx, "icon-rotation").await {
+            let resume_delay = startup_icon_rotation(&ctx).await;
+            tokio::spawn(run_icon_rotation_loop(ctx.clone(), resume_delay));
+        }
+
+        spawn_background_tasks(&ctx).await;
+    }
+
+    async fn message(&self, ctx: Context, message: Message) {
+        if let Err(error) = handle_text_command(&ctx, &message).await {
+            error!("Error handling text command: {error}");
+        }
+    }
+
+    async fn guild_create(&self, ctx: Context, guild: Guild, is_new: Option<bool>) {
+        let Some(config) = get_config(&ctx).await else {
+            return;
+        };
+
+        if guild.id == config.guild {
+            ctx.data
+                .write()
+                .await
+                .insert::<GuildFeatures>(guild.features);
+        } else {
+            handle_unconfigured_guild(&ctx, &config, &guild, is_new.unwrap_or_default()).await;
+        }
+    }
+
+    async fn guild_role_delete(
+        &self,
+        ctx: Context,
+        guild_id: GuildId,
+        removed_role_id: RoleId,
+        _removed_role_data_if_available: Option<Role>,
+    ) {
+        let Some(config) = get_config(&ctx).await else {
+            return;
+        };
+
+        let is_toggle_role = removed_role_id == config.alerts
+            || config
+                .alert_presets
+                .values()
+                .any(|roles| roles.contains(&removed_role_id));
+        if guild_id != config.guild || !is_toggle_role {
+            return;
+        }
+
+        ctx.data
+            .write()
+            .await
+            .entry::<DeletedRoles>()
+            .or_default()
+            .insert(removed_role_id);
+
+        let content = format!(
+            "The alerts role <@&{removed_role_id}> ({removed_role_id}) was deleted, /alerts will report it as unavailable until the config is updated"
+        );
+        warn!("{content}");
+        notify_staff(&ctx, &config, content).await;
+    }
+
+    async fn guild_update(
+        &self,
+        ctx: Context,
+        _old_data_if_available: Option<Guild>,
+        new_data: PartialGuild,
+    ) {
+        let Some(config) = get_config(&ctx).await else {
+            return;
+        };
+
+        if new_data.id == config.guild {
+            let features = new_data.features.clone();
+            ctx.data.write().await.insert::<GuildFeatures>(features);
+            track_external_icon_change(&ctx, &config, &new_data).await;
+        }
+    }
+
+    async fn guild_member_addition(&self, ctx: Context, new_member: Member) {
+        let Some(config) = get_config(&ctx).await else {
+            return;
+        };
+
+        if new_member.guild_id != config.guild || config.member_milestones.is_empty() {
+            return;
+        }
+
+        let member_count = ctx
+            .cache
+            .guild(config.guild)
+            .map(|guild| guild.member_count);
+        if let Err(error) = check_member_milestone(&ctx, &config, member_count).await {
+            error!("Error checking member milestones: {error}");
+        }
+    }
+
+    async fn guild_member_update(
+        &self,
+        ctx: Context,
+        _old_if_available: Option<Member>,
+        _new: Option<Member>,
+        event: GuildMemberUpdateEvent,
+    ) {
+        let current_user_id = ctx.cache.current_user().id;
+        if event.user.id != current_user_id {
+            return;
+        }
+
+        if get_config(&ctx)
+            .await
+            .is_none_or(|config| config.guild != event.guild_id)
+        {
+            return;
+        }
+
+        let nick = event.nick.unwrap_or_default();
+        enforce_bot_nickname(&ctx, current_user_id, Some(nick)).await;
+    }
+
+    async fn voice_state_update(&self, ctx: Context, old: Option<VoiceState>, new: VoiceState) {
+        let Some(config) = get_config(&ctx).await else {
+            return;
+        };
+
+        refresh_presence(&ctx).await;
+
+        if maintenance_active(&ctx).await {
+            return;
+        }
+
+        let Some(member) = new.member else {
+            return;
+        };
+
+        let Some(guild_id) = new.guild_id else {
+            return;
+        };
+
+        if guild_id != config.guild {
+            return;
+        }
+
+        let pairs = voice_pairs(&config);
+        let old_channel_id = old.as_ref().and_then(|old| old.channel_id);
+        let old_pair = old_channel_id.and_then(|channel_id| find_voice_pair(&pairs, channel_id));
+        let new_pair = new
+            .channel_id
+            .and_then(|channel_id| find_voice_pair(&pairs, channel_id));
+
+        if let (Some(new_channel_id), Some(pair)) = (new.channel_id, new_pair) {
+            /* User joined the voice channel, giving view permission */
+            if new_channel_id == pair.voice {
+                info!("[{}] joined the voice channel", member.display_name());
+                info!("Giving access to the video channel");
+
+                let target = PermissionOverwrite {
+                    allow: Permissions::VIEW_CHANNEL,
+                    deny:  Permissions::empty(),
+                    kind:  PermissionOverwriteType::Member(new.user_id),
+                };
+
+                if let Err(error) = pair.video.create_permission(&ctx, target).await {
+                    error!("Error updating channel permissions: {error}");
+                };
+            }
+
+            /* User started streaming in the voice channel, or joined it already streaming */
+            let decision = stream_move(
+                old_channel_id,
+                new_channel_id,
+                new.self_stream,
+                pair,
+                config.move_on_join_streaming,
+            );
+            match decision {
+                StreamMove::Stay => {}
+                StreamMove::JoinedStreaming => info!(
+                    "[{}] joined already streaming, not moving",
+                    member.display_name()
+                ),
+                StreamMove::Move if video_opted_out(&ctx, new.user_id).await => {
+                    info!("[{}] opted out of being moved", member.display_name());
+                }
+                StreamMove::Move => {
+                    let result = guild_id.move_member(&ctx, new.user_id, pair.video).await;
+                    if let Err(error) = result {
+                        report_move_failure(&ctx, &config, &error).await;
+                    }
+                }
+            }
+        }
+
+        /* User left the voice/video channels of a pair, remove view permission */
+        let Some(old_pair) = old_pair else {
+            return;
+        };
+
+        if new_pair == Some(old_pair) {
+            return;
+        }
+
+        info!("[{}] left the video channel", member.display_name());
+        info!("Removing access to the video channel");
+
+        let permission_type = PermissionOverwriteType::Member(new.user_id);
+        let result = old_pair
+            .video
+            .delete_permission(&ctx, permission_type)
+            .await;
+
+        if let Err(error) = result {
+            error!("Error updating channel permissions: {error}");
+        }
+
+        if config.sweep_empty_video_channels {
+            sweep_empty_video_channel(&ctx, &config, guild_id, old_pair, new.user_id).await;
+        }
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        match interaction {
+            Interaction::Command(command) => {
+                if let Err(error) = handle_command(&ctx, &command).await {
+                    error!("Error handling command: {error}");
+                    report_command_error(&ctx, &command, &error).await;
+                }
+            }
+            Interaction::Autocomplete(command) => {
+                if let Err(error) = handle_autocomplete(&ctx, &command).await {
+                    error!("Error handling autocomplete: {error}");
+                }
+            }
+            Interaction::Component(component) => {
+                if let Err(error) = handle_component(&ctx, &component).await {
+                    error!("Error handling component: {error}");
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Applies `bot_nickname` when the bot's nickname differs from it, fetching the current one if
+/// not provided (an empty string means no nickname).
+async fn enforce_bot_nickname(ctx: &Context, user_id: UserId, current: Option<String>) {
+    let Some(config) = get_config(ctx).await else {
+        return;
+    };
+
+    let Some(nickname) = config.bot_nickname.filter(|nickname| !nickname.is_empty()) else {
+        return;
+    };
+
+    let current = match current {
+        Some(current) => current,
+        None => match config.guild.member(&ctx.http, user_id).await {
+            Ok(member) => member.nick.unwrap_or_default(),
+            Err(error) => {
+                error!("Error fetching the bot's guild member: {error}");
+                return;
+            }
+        },
+    };
+
+    if current == nickname {
+        return;
+    }
+
+    match config.guild.edit_nickname(&ctx.http, Some(&nickname)).await {
+        Ok(()) => info!("Changed the bot nickname from '{current}' to '{nickname}'"),
+        Err(error) => error!("Error changing the bot nickname: {error}"),
+    }
+}
+
+/// Applies `guild_name` when the server's name differs from it.
+async fn enforce_guild_name(ctx: &Context) {
+    let Some(config) = get_config(ctx).await else {
+        return;
+    };
+
+    let Some(name) = config.guild_name.filter(|name| !name.is_empty()) else {
+        return;
+    };
+
+    let current = match config.guild.to_partial_guild(&ctx.http).await {
+        Ok(guild) => guild.name,
+        Err(error) => {
+            error!("Error fetching the guild name: {error}");
+            return;
+        }
+    };
+
+    if current == name {
+        return;
+    }
+
+    let builder = EditGuild::new().name(&name);
+    match config.guild.edit(&ctx.http, builder).await {
+        Ok(_) => info!("Changed the guild name from '{current}' to '{name}'"),
+        Err(error) => error!("Error changing the guild name: {error}"),
+    }
+}
+
+/// Tells the user something went wrong and alerts the staff, without replacing a response that
+/// was already sent before the error.
+async fn report_command_error(ctx: &Context, command: &CommandInteraction, error: &Report) {
+    const CONTENT: &str = "Something went wrong, staff have been notified.";
+
+    if let Some(config) = get_config(ctx).await {
+        let content = format!(
+            "Error handling /{} for {}: {error}",
+            command.data.name, command.user.name
+        );
+        notify_staff(ctx, &config, content).await;
+    }
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(CONTENT)
+            .ephemeral(true),
+    );
+
+    if command.create_response(&ctx.http, response).await.is_ok() {
+        return;
+    }
+
+    /* Already acknowledged, only fill in a deferred response that is still empty */
+    let Ok(message) = command.get_response(&ctx.http).await else {
+        return;
+    };
+
+    if message.content.is_empty() && message.embeds.is_empty() && message.attachments.is_empty() {
+        let builder = EditInteractionResponse::new().content(CONTENT);
+        if let Err(error) = command.edit_response(&ctx.http, builder).await {
+            error!("Error reporting command failure: {error}");
+        }
+    }
+}
+
+/// A subcommand taking a required, autocompleted icon filename.
+fn icon_filename_subcommand(name: &str, description: &str, option: &str) -> CreateCommandOption {
+    CreateCommandOption::new(CommandOptionType::SubCommand, name, description).add_sub_option(
+        CreateCommandOption::new(CommandOptionType::String, "filename", option)
+            .required(true)
+            .set_autocomplete(true),
+    )
+}
+
+fn plain_subcommand(name: &str, description: &str) -> CreateCommandOption {
+    CreateCommandOption::new(CommandOptionType::SubCommand, name, description)
+}
+
+/// The `/icon` subcommands that inspect the pool and rotation.
+fn icon_inspect_subcommands() -> Vec<CreateCommandOption> {
+    vec![
+        icon_filename_subcommand(
+            "preview",
+            "Preview an icon without applying it",
+            "The icon to preview",
+        )
+        .add_sub_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "public",
+            "Post the preview in this channel instead of only to you",
+        )),
+        plain_subcommand(
+            "unused",
+            "List the icons that have not been shown yet this cycle",
+        ),
+        plain_subcommand(
+            "validate",
+            "Check every icon file for problems that would skip it",
+        )
+        .add_sub_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "retry_rejected",
+            "Forget the icons Discord rejected so the rotation tries them again",
+        )),
+        plain_subcommand("delay-test", "Sample the configured rotation delay").add_sub_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "count",
+                "How many delays to sample",
+            )
+            .min_int_value(1)
+            .max_int_value(20),
+        ),
+    ]
+}
+
+/// The `/icon` subcommands that change the pool or what is shown.
+fn icon_control_subcommands() -> Vec<CreateCommandOption> {
+    vec![
+        icon_filename_subcommand(
+            "remove",
+            "Delete an icon from the rotation",
+            "The icon to delete",
+        ),
+        icon_filename_subcommand(
+            "pin",
+            "Apply an icon and keep it until unpinned",
+            "The icon to pin",
+        ),
+        plain_subcommand("unpin", "Resume the icon rotation"),
+        plain_subcommand("reroll", "Replace the current icon with a new random one"),
+        plain_subcommand("debug", "Dump the in-memory rotation state as JSON"),
+        CreateCommandOption::new(
+            CommandOptionType::SubCommandGroup,
+            "breaker",
+            "Inspect or reset the icon upload circuit breaker",
+        )
+        .add_sub_option(plain_subcommand(
+            "status",
+            "Show the failure count and whether rotation is paused",
+        ))
+        .add_sub_option(plain_subcommand(
+            "reset",
+            "Clear the failures and resume rotation right away",
+        )),
+        plain_subcommand(
+            "interval",
+            "Change the random delay range between icon changes",
+        )
+        .add_sub_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "min_hours",
+                "The shortest delay in hours",
+            )
+            .required(true)
+            .min_int_value(0),
+        )
+        .add_sub_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "max_hours",
+                "The longest delay in hours",
+            )
+            .required(true)
+            .min_int_value(1),
+        ),
+        plain_subcommand(
+            "backup",
+            "Save the current server icon to the backup directory",
+        ),
+        plain_subcommand("restore", "Reapply the most recent icon backup"),
+        plain_subcommand("import-urls", "Download icons from a list of image URLs")
+            .add_sub_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "urls",
+                "Image URLs separated by spaces or newlines",
+            ))
+            .add_sub_option(CreateCommandOption::new(
+                CommandOptionType::Attachment,
+                "file",
+                "A text file with one image URL per line",
+            )),
+        icon_filename_subcommand(
+            "boost",
+            "Temporarily make an icon more likely to be picked",
+            "The icon to boost",
+        )
+        .add_sub_option(
+            CreateCommandOption::new(
+                CommandOptionType::Number,
+                "multiplier",
+                "How many times more likely the icon is picked",
+            )
+            .required(true)
+            .min_number_value(0.1)
+            .max_number_value(100.0),
+        )
+        .add_sub_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "hours",
+                "How long the boost lasts",
+            )
+            .required(true)
+            .min_int_value(1)
+            .max_int_value(24 * 30),
+        ),
+        plain_subcommand("poll", "Let the community vote on the next icon").add_sub_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "count",
+                "How many candidate icons to offer",
+            )
+            .min_int_value(2)
+            .max_int_value(MAX_POLL_OPTIONS as u64),
+        ),
+    ]
+}
+
+fn icon_command() -> CreateCommand {
+    let options = icon_inspect_subcommands()
+        .into_iter()
+        .chain(icon_control_subcommands())
+        .collect();
+
+    CreateCommand::new("icon")
+        .description("Manage the server icon rotation")
+        .default_member_permissions(Permissions::MANAGE_GUILD)
+        .set_options(options)
+}
+
+fn loglevel_command() -> CreateCommand {
+    CreateCommand::new("loglevel")
+        .description("Change how verbose the bot's logs are for a while")
+        .default_member_permissions(Permissions::MANAGE_GUILD)
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "level",
+                "The level for the bot's own logs, or default to go back",
+            )
+            .required(true)
+            .add_string_choice("trace", "trace")
+            .add_string_choice("debug", "debug")
+            .add_string_choice("info", "info")
+            .add_string_choice("warn", "warn")
+            .add_string_choice("error", "error")
+            .add_string_choice("default", "default"),
+        )
+}
+
+fn maintenance_command() -> CreateCommand {
+    CreateCommand::new("maintenance")
+        .description("Pause or resume the bot's automation")
+        .default_member_permissions(Permissions::MANAGE_GUILD)
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "mode",
+                "Whether maintenance mode is on or off",
+            )
+            .required(true)
+            .add_string_choice("on", "on")
+            .add_string_choice("off", "off"),
+        )
+}
+
+fn commands() -> Vec<CreateCommand> {
+    vec![
+        CreateCommand::new("alerts")
+            .description("Toggle your alert roles and how alerts reach you")
+            .add_option(
+                plain_subcommand("toggle", "Toggle the alerts role for yourself").add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::String,
+                        "preset",
+                        "Toggle every role in a preset at once",
+                    )
+                    .set_autocomplete(true),
+                ),
+            )
+            .add_option(
+                plain_subcommand(
+                    "prefs",
+                    "View or set how you want to be notified about alerts",
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::String,
+                        "delivery",
+                        "How alerts should reach you",
+                    )
+                    .add_string_choice("Role mentions", "mentions")
+                    .add_string_choice("Direct messages", "dm")
+                    .add_string_choice("No notifications", "none"),
+                ),
+            ),
+        CreateCommand::new("feedback")
+            .description("Send feedback or suggestions to the staff")
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "message",
+                    "Your feedback or suggestion",
+                )
+                .required(true)
+                .max_length(1000),
+            ),
+        CreateCommand::new("sync")
+            .description("Re-register all slash commands")
+            .default_member_permissions(Permissions::MANAGE_GUILD),
+        CreateCommand::new("stats")
+            .description("Show server icon rotation statistics")
+            .default_member_permissions(Permissions::MANAGE_GUILD),
+        icon_command(),
+        CreateCommand::new("config")
+            .description("Inspect the bot configuration")
+            .default_member_permissions(Permissions::MANAGE_GUILD)
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "diff",
+                "Show what reloading the config file would change",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "raw",
+                "Show the config file as it is on disk, with the token redacted",
+            )),
+        CreateCommand::new("version").description("Show which build of the bot is running"),
+        CreateCommand::new("guilds")
+            .description("List every server the bot is in (application owner only)"),
+        CreateCommand::new("serverinfo")
+            .description("Show the server's counts and which icon and banner features it has"),
+        CreateCommand::new("video")
+            .description("Choose whether you are moved to the video channel when streaming")
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "optout",
+                "Stop being moved to the video channel when you start streaming",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "optin",
+                "Be moved to the video channel again when you start streaming",
+            )),
+        CreateCommand::new("theme")
+            .description("Apply a theme pack's icon, banner and name together")
+            .default_member_permissions(Permissions::MANAGE_GUILD)
+            .add_option(plain_subcommand("list", "List the configured theme packs"))
+            .add_option(
+                plain_subcommand("apply", "Apply a theme pack").add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::String,
+                        "name",
+                        "The theme pack to apply",
+                    )
+                    .required(true)
+                    .set_autocomplete(true),
+                ),
+            ),
+        loglevel_command(),
+        maintenance_command(),
+    ]
+}
+
+async fn sync_commands(ctx: &Context) -> Result<Vec<Command>> {
+    Ok(Command::set_global_commands(&ctx.http, commands()).await?)
+}
+
+/// The invoked subcommand name, if the command has subcommands.
+fn subcommand(command: &CommandInteraction) -> Option<&str> {
+    command
+        .data
+        .options
+        .first()
+        .filter(|option| {
+            matches!(
+                option.value,
+                CommandDataOptionValue::SubCommand(_) | CommandDataOptionValue::SubCommandGroup(_)
+            )
+        })
+        .map(|option| option.name.as_str())
+}
+
+/// The subcommand invoked inside a subcommand group, e.g. `reset` for `/icon breaker reset`.
+fn group_subcommand(command: &CommandInteraction) -> Option<&str> {
+    match command.data.options.first().map(|option| &option.value) {
+        Some(CommandDataOptionValue::SubCommandGroup(options)) => {
+            options.first().map(|option| option.name.as_str())
+        }
+        _ => None,
+    }
+}
+
+/// The options of the invoked subcommand, or the top-level options when there is none.
+fn command_options(command: &CommandInteraction) -> &[CommandDataOption] {
+    match command.data.options.first().map(|option| &option.value) {
+        Some(CommandDataOptionValue::SubCommand(options)) => options,
+        _ => &command.data.options,
+    }
+}
+
+fn string_option<'a>(command: &'a CommandInteraction, name: &str) -> Option<&'a str> {
+    command_options(command)
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_str())
+}
+
+fn attachment_option<'a>(command: &'a CommandInteraction, name: &str) -> Option<&'a Attachment> {
+    command_options(command)
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_attachment_id())
+        .and_then(|id| command.data.resolved.attachments.get(&id))
+}
+
+fn number_option(command: &CommandInteraction, name: &str) -> Option<f64> {
+    command_options(command)
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_f64())
+}
+
+fn integer_option(command: &CommandInteraction, name: &str) -> Option<i64> {
+    command_options(command)
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_i64())
+}
+
+fn bool_option(command: &CommandInteraction, name: &str) -> Option<bool> {
+    command_options(command)
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_bool())
+}
+
+/// Whether the invoking member can manage the guild, which gates the staff-only commands.
+fn is_staff(command: &CommandInteraction) -> bool {
+    command
+        .member
+        .as_ref()
+        .and_then(|member| member.permissions)
+        .is_some_and(Permissions::manage_guild)
+}
+
+async fn reply_ephemeral(
+    ctx: &Context,
+    command: &CommandInteraction,
+    content: impl Into<String>,
+) -> Result<()> {
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(content)
+            .ephemeral(true),
+    );
+
+    command.create_response(&ctx.http, response).await?;
+    Ok(())
+}
+
+async fn handle_autocomplete(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    let Some(focused) = command.data.autocomplete() else {
+        return Ok(());
+    };
+
+    let choices: Vec<String> = match (command.data.name.as_str(), focused.name) {
+        ("alerts", "preset") => get_config(ctx)
+            .await
+            .map(|config| config.alert_presets.into_keys().collect())
+            .unwrap_or_default(),
+        ("theme", "name") => get_config(ctx)
+            .await
+            .map(|config| config.themes.into_keys().collect())
+            .unwrap_or_default(),
+        ("icon", "filename") => get_config(ctx)
+            .await
+            .map(|config| icon_names(&config))
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    };
+
+    let query = focused.value.to_lowercase();
+    let response = choices
+        .into_iter()
+        .filter(|choice| choice.to_lowercase().contains(&query))
+        .take(25)
+        .fold(CreateAutocompleteResponse::new(), |response, choice| {
+            response.add_string_choice(choice.clone(), choice)
+        });
+
+    command
+        .create_response(&ctx.http, CreateInteractionResponse::Autocomplete(response))
+        .await?;
+
+    Ok(())
+}
+
+/// Commands restricted to staff, whose every invocation goes to the audit channel.
+const ADMIN_COMMANDS: &[&str] = &[
+    "config",
+    "icon",
+    "loglevel",
+    "maintenance",
+    "stats",
+    "sync",
+    "theme",
+];
+
+/// Formats the options as `name: value`, flattening subcommands into `subcommand name: value`.
+fn describe_options(options: &[CommandDataOption]) -> Vec<String> {
+    options
+        .iter()
+        .flat_map(|option| match &option.value {
+            CommandDataOptionValue::SubCommand(options)
+            | CommandDataOptionValue::SubCommandGroup(options) => {
+                let nested = describe_options(options);
+                if nested.is_empty() {
+                    vec![option.name.clone()]
+                } else {
+                    nested
+                        .into_iter()
+                        .map(|nested| format!("{} {nested}", option.name))
+                        .collect()
+                }
+            }
+            CommandDataOptionValue::String(value) => vec![format!("{}: {value}", option.name)],
+            CommandDataOptionValue::Integer(value) => vec![format!("{}: {value}", option.name)],
+            CommandDataOptionValue::Number(value) => vec![format!("{}: {value}", option.name)],
+            CommandDataOptionValue::Boolean(value) => vec![format!("{}: {value}", option.name)],
+            CommandDataOptionValue::User(id) => vec![format!("{}: <@{id}>", option.name)],
+            CommandDataOptionValue::Channel(id) => vec![format!("{}: <#{id}>", option.name)],
+            CommandDataOptionValue::Role(id) => vec![format!("{}: <@&{id}>", option.name)],
+            _ => vec![format!("{}: (attachment or other)", option.name)],
+        })
+        .collect()
+}
+
+/// Posts who ran an admin command, and with which arguments, to `audit_channel`.
+async fn audit_command(ctx: &Context, command: &CommandInteraction) {
+    if !ADMIN_COMMANDS.contains(&command.data.name.as_str()) {
+        return;
+    }
+
+    let Some(channel_id) = get_config(ctx)
+        .await
+        .and_then(|config| config.audit_channel)
+    else {
+        return;
+    };
+
+    let arguments = describe_options(&command.data.options);
+    let embed = CreateEmbed::new()
+        .title(format!("/{}", command.data.name))
+        .description(if arguments.is_empty() {
+            String::from("No arguments")
+        } else {
+            capped_list(&arguments, 10)
+        })
+        .field("Invoker", format!("<@{}>", command.user.id), true)
+        .field("Channel", format!("<#{}>", command.channel_id), true)
+        .timestamp(Timestamp::now());
+
+    let message = CreateMessage::new()
+        .embed(embed)
+        .allowed_mentions(CreateAllowedMentions::new());
+
+    if let Err(error) = channel_id.send_message(&ctx.http, message).await {
+        error!("Error posting to the audit channel: {error}");
+    }
+}
+
+async fn handle_command(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    /* Posted in the background so the interaction is still answered in time */
+    let (audit_ctx, audit_command_data) = (ctx.clone(), command.clone());
+    tokio::spawn(async move { audit_command(&audit_ctx, &audit_command_data).await });
+
+    match command.data.name.as_str() {
+        "alerts" => handle_alerts_command(ctx, command).await?,
+        "feedback" => handle_feedback_command(ctx, command).await?,
+        "icon" => handle_icon_command(ctx, command).await?,
+        "stats" => handle_stats_command(ctx, command).await?,
+        "sync" => handle_sync_command(ctx, command).await?,
+        "maintenance" => handle_maintenance_command(ctx, command).await?,
+        "loglevel" => handle_loglevel_command(ctx, command).await?,
+        "video" => handle_video_command(ctx, command).await?,
+        "serverinfo" => handle_serverinfo_command(ctx, command).await?,
+        "version" => handle_version_command(ctx, command).await?,
+        "guilds" => handle_guilds_command(ctx, command).await?,
+        "config" => handle_config_command(ctx, command).await?,
+        "theme" => handle_theme_command(ctx, command).await?,
+        _ => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("Unknown command")
+                    .ephemeral(true),
+            );
+
+            command.create_response(&ctx.http, response).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Shows or stores the member's alert delivery preference; the roles themselves are untouched.
+async fn handle_alerts_prefs_command(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    let Some(config) = get_config(ctx).await else {
+        return reply_ephemeral(ctx, command, "Configuration not found").await;
+    };
+
+    if command.guild_id != Some(config.guild) {
+        return reply_ephemeral(ctx, command, "This command is not available here").await;
+    }
+
+    let user_id = command.user.id;
+    let Some(delivery) = string_option(command, "delivery") else {
+        let delivery = ctx
+            .data
+            .read()
+            .await
+            .get::<State>()
+            .and_then(|state| state.alert_prefs.get(&user_id).copied())
+            .unwrap_or_default();
+        let content = format!("You receive alerts as {}", delivery.describe());
+        return reply_ephemeral(ctx, command, content).await;
+    };
+
+    let Some(delivery) = AlertDelivery::parse(delivery) else {
+        return reply_ephemeral(ctx, command, "Please choose mentions, dm or none").await;
+    };
+
+    update_state(ctx, |state| {
+        if delivery == AlertDelivery::default() {
+            state.alert_prefs.remove(&user_id);
+        } else {
+            state.alert_prefs.insert(user_id, delivery);
+        }
+    })
+    .await?;
+
+    info!(
+        "[{}] set their alert delivery to {}",
+        command.user.name,
+        delivery.describe()
+    );
+    let content = format!("You will now receive alerts as {}", delivery.describe());
+    reply_ephemeral(ctx, command, content).await
+}
+
+async fn handle_alerts_command(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    if subcommand(command) == Some("prefs") {
+        return handle_alerts_prefs_command(ctx, command).await;
+    }
+
+    let Some(config) = get_config(ctx).await else {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("Configuration not found")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    };
+
+    let Some(guild_id) = command.guild_id else {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("This command can only be used in a guild")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    };
+
+    if guild_id != config.guild {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("This command is not available in this guild")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    if let Some(preset) = string_option(command, "preset") {
+        return handle_alerts_preset(ctx, command, &config, guild_id, preset).await;
+    }
+
+    if config.alerts.get() == 0 {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("Alerts role is not configured. Please contact an administrator.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    let (message, success, has_role) =
+        toggle_alerts_role(ctx, &config, guild_id, command.user.id).await?;
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(message)
+            .ephemeral(!success || config.alerts_ephemeral),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    if success {
+        let action = if has_role { "removed" } else { "added" };
+        info!("[{}] {} the alerts role", command.user.name, action);
+    }
+
+    Ok(())
+}
+
+/// Runs the request up to `attempts` times (at least once), waiting a moment between tries, and
+/// returns the last error once they are exhausted.
+async fn with_retries<T, F, Fut>(attempts: u32, mut request: F) -> serenity::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = serenity::Result<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        match request().await {
+            Ok(value) => return Ok(value),
+            Err(error) if attempt < attempts => {
+                warn!("Request failed (attempt {attempt} of {attempts}), retrying: {error}");
+                attempt += 1;
+                sleep(Duration::from_millis(500)).await;
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Configured toggle roles that were deleted from the guild while the bot was running.
+struct DeletedRoles;
+
+impl TypeMapKey for DeletedRoles {
+    type Value = HashSet<RoleId>;
+}
+
+async fn role_deleted(ctx: &Context, role_id: RoleId) -> bool {
+    ctx.data
+        .read()
+        .await
+        .get::<DeletedRoles>()
+        .is_some_and(|roles| roles.contains(&role_id))
+}
+
+/// Toggles the alerts role, returning the reply, whether it worked and whether the role was held.
+async fn toggle_alerts_role(
+    ctx: &Context,
+    config: &Config,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> Result<(&'static str, bool, bool)> {
+    if role_deleted(ctx, config.alerts).await {
+        return Ok((
+            "The alerts role no longer exists. Please contact an administrator.",
+            false,
+            false,
+        ));
+    }
+
+    let attempts = config.alerts_retry_attempts;
+    let member = with_retries(attempts, || guild_id.member(&ctx.http, user_id)).await?;
+    let has_role = member.roles.contains(&config.alerts);
+
+    let (message, success) = if has_role {
+        match with_retries(attempts, || member.remove_role(&ctx.http, config.alerts)).await {
+            Ok(()) => ("Successfully removed the alerts role!", true),
+            Err(_) => (
+                "Failed to remove the alerts role. Please contact an administrator.",
+                false,
+            ),
+        }
+    } else {
+        match with_retries(attempts, || member.add_role(&ctx.http, config.alerts)).await {
+            Ok(()) => ("Successfully added the alerts role!", true),
+            Err(_) => (
+                "Failed to add the alerts role. Please contact an administrator.",
+                false,
+            ),
+        }
+    };
+
+    Ok((message, success, has_role))
+}
+
+/// Handles the text-command fallback (e.g. `!alerts`) for members who prefer it.
+async fn handle_text_command(ctx: &Context, message: &Message) -> Result<()> {
+    let Some(config) = get_config(ctx).await else {
+        return Ok(());
+    };
+
+    let Some(prefix) = config
+        .text_command_prefix
+        .as_deref()
+        .filter(|prefix| !prefix.is_empty())
+    else {
+        return Ok(());
+    };
+
+    if message.author.bot || message.guild_id != Some(config.guild) {
+        return Ok(());
+    }
+
+    let Some(name) = message
+        .content
+        .strip_prefix(prefix)
+        .and_then(|rest| rest.split_whitespace().next())
+    else {
+        return Ok(());
+    };
+
+    if !name.eq_ignore_ascii_case("alerts") {
+        return Ok(());
+    }
+
+    if config.alerts.get() == 0 {
+        message
+            .reply(
+                &ctx.http,
+                "Alerts role is not configured. Please contact an administrator.",
+            )
+            .await?;
+        return Ok(());
+    }
+
+    let (reply, success, has_role) =
+        toggle_alerts_role(ctx, &config, config.guild, message.author.id).await?;
+    let reacted = success && confirm_with_reaction(ctx, &config, message).await;
+    if !(reacted && config.success_reaction_only) {
+        message.reply(&ctx.http, reply).await?;
+    }
+
+    if success {
+        let action = if has_role { "removed" } else { "added" };
+        info!("[{}] {} the alerts role", message.author.name, action);
+    }
+
+    Ok(())
+}
+
+/// Reacts with `success_reaction`, returning whether it was added so the caller can fall back
+/// to the text reply.
+async fn confirm_with_reaction(ctx: &Context, config: &Config, message: &Message) -> bool {
+    let Some(emoji) = config.success_reaction.as_deref() else {
+        return false;
+    };
+
+    let Ok(reaction) = emoji.parse::<ReactionType>() else {
+        warn!("'{emoji}' is not a valid success_reaction");
+        return false;
+    };
+
+    match message.react(&ctx.http, reaction).await {
+        Ok(_) => true,
+        Err(error) => {
+            warn!("Error reacting to a text command: {error}");
+            false
+        }
+    }
+}
+
+/// A member's roles after toggling a preset.
+#[derive(Debug, PartialEq, Eq)]
+struct PresetToggle {
+    roles:   Vec<RoleId>,
+    removed: bool,
+    changed: usize,
+}
+
+/// Removes the whole preset if the member already has every role, otherwise adds the missing
+/// ones, keeping the member's other roles.
+fn toggle_preset_roles(current: &[RoleId], preset: &[RoleId]) -> PresetToggle {
+    let removed = preset.iter().all(|role| current.contains(role));
+    let mut roles = current.to_vec();
+    let before = roles.len();
+    if removed {
+        roles.retain(|role| !preset.contains(role));
+    } else {
+        for role in preset {
+            if !roles.contains(role) {
+                roles.push(*role);
+            }
+        }
+    }
+
+    let changed = before.abs_diff(roles.len());
+    PresetToggle {
+        roles,
+        removed,
+        changed,
+    }
+}
+
+async fn handle_alerts_preset(
+    ctx: &Context,
+    command: &CommandInteraction,
+    config: &Config,
+    guild_id: GuildId,
+    preset: &str,
+) -> Result<()> {
+    let Some(roles) = config
+        .alert_presets
+        .get(preset)
+        .filter(|roles| !roles.is_empty())
+    else {
+        let content = format!("Unknown alerts preset '{preset}'");
+        return reply_ephemeral(ctx, command, content).await;
+    };
+
+    for role in roles {
+        if role_deleted(ctx, *role).await {
+            let content = format!(
+                "A role in the '{preset}' preset no longer exists. Please contact an administrator."
+            );
+            return reply_ephemeral(ctx, command, content).await;
+        }
+    }
+
+    let mut member = guild_id.member(&ctx.http, command.user.id).await?;
+    let toggle = toggle_preset_roles(&member.roles, roles);
+    let action = if toggle.removed { "removed" } else { "added" };
+
+    /* One edit with the whole role list, so the preset is applied completely or not at all */
+    let builder = EditMember::new().roles(toggle.roles);
+    let result = member.edit(&ctx.http, builder).await;
+    let failed = result.is_err();
+    let content = match result {
+        Ok(()) => format!("Successfully {action} the '{preset}' alerts preset!"),
+        Err(error) => {
+            error!("Error toggling the '{preset}' alerts preset: {error}");
+            format!(
+                "Failed to update the '{preset}' alerts preset. Please contact an administrator."
+            )
+        }
+    };
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(content)
+            .ephemeral(failed || config.alerts_ephemeral),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    if !failed {
+        info!(
+            "[{}] {} {} roles from the '{preset}' alerts preset",
+            command.user.name, action, toggle.changed
+        );
+    }
+
+    Ok(())
+}
+
+async fn handle_feedback_command(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    let Some(config) = get_config(ctx).await else {
+        return reply_ephemeral(ctx, command, "Configuration not found").await;
+    };
+
+    if command.guild_id != Some(config.guild) {
+        return reply_ephemeral(ctx, command, "This command is not available here").await;
+    }
+
+    let Some(channel_id) = config.feedback_channel else {
+        return reply_ephemeral(ctx, command, "Feedback is not enabled on this server").await;
+    };
+
+    let Some(message) = string_option(command, "message") else {
+        return reply_ephemeral(ctx, command, "Please provide a feedback message").await;
+    };
+
+    let cooldown = Duration::from_secs(config.feedback_cooldown_seconds);
+    let remaining = ctx
+        .data
+        .read()
+        .await
+        .get::<FeedbackCooldowns>()
+        .and_then(|cooldowns| cooldowns.get(&command.user.id))
+        .and_then(|sent_at| cooldown.checked_sub(sent_at.elapsed()))
+        .filter(|remaining| !remaining.is_zero());
+
+    if let Some(remaining) = remaining {
+        let content = format!(
+            "Please wait {} more seconds before sending more feedback",
+            remaining.as_secs() + 1
+        );
+        return reply_ephemeral(ctx, command, content).await;
+    }
+
+    let author = CreateEmbedAuthor::new(command.user.tag()).icon_url(command.user.face());
+    let embed = CreateEmbed::new()
+        .author(author)
+        .title("Feedback")
+        .description(message)
+        .footer(CreateEmbedFooter::new(format!(
+            "User ID: {}",
+            command.user.id
+        )))
+        .timestamp(Timestamp::now());
+
+    if let Err(error) = channel_id
+        .send_message(&ctx.http, CreateMessage::new().embed(embed))
+        .await
+    {
+        error!("Error forwarding feedback: {error}");
+        return reply_ephemeral(
+            ctx,
+            command,
+            "Failed to send your feedback. Please contact an administrator.",
+        )
+        .await;
+    }
+
+    ctx.data
+        .write()
+        .await
+        .entry::<FeedbackCooldowns>()
+        .or_default()
+        .insert(command.user.id, Instant::now());
+
+    info!("[{}] sent feedback", command.user.name);
+    reply_ephemeral(ctx, command, "Thank you for your feedback!").await
+}
+
+/// Config fields holding credentials, redacted wherever the config is shown or logged.
+const SECRET_FIELDS: &[&str] = &["token", "http_api_token"];
+
+/// Lists the fields that differ between two configs as `field: old → new`, redacting
+/// `SECRET_FIELDS`.
+fn config_diff(old: &Config, new: &Config) -> Result<Vec<String>> {
+    let toml::Value::Table(old) = toml::Value::try_from(old)? else {
+        bail!("config did not serialize to a table");
+    };
+    let toml::Value::Table(new) = toml::Value::try_from(new)? else {
+        bail!("config did not serialize to a table");
+    };
+
+    let fields = old.keys().chain(new.keys()).collect::<BTreeSet<_>>();
+    let describe = |value: Option<&toml::Value>| {
+        let text = value.map_or_else(|| String::from("unset"), ToString::to_string);
+        if text.chars().count() > 80 {
+            format!("{}...", text.chars().take(80).collect::<String>())
+        } else {
+            text
+        }
+    };
+
+    Ok(fields
+        .into_iter()
+        .filter(|field| old.get(*field) != new.get(*field))
+        .map(|field| {
+            if SECRET_FIELDS.contains(&field.as_str()) {
+                format!("`{field}`: (redacted) → (redacted)")
+            } else {
+                format!(
+                    "`{field}`: {} → {}",
+                    describe(old.get(field)),
+                    describe(new.get(field))
+                )
+            }
+        })
+        .collect())
+}
+
+async fn handle_config_command(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    let Some(config) = get_config(ctx).await else {
+        return reply_ephemeral(ctx, command, "Configuration not found").await;
+    };
+
+    if command.guild_id != Some(config.guild) {
+        return reply_ephemeral(ctx, command, "This command is not available here").await;
+    }
+
+    if !is_staff(command) {
+        return reply_ephemeral(ctx, command, "You need the Manage Server permission").await;
+    }
+
+    match subcommand(command) {
+        Some("diff") => handle_config_diff(ctx, command, &config).await,
+        Some("raw") => handle_config_raw(ctx, command).await,
+        _ => reply_ephemeral(ctx, command, "Unknown subcommand").await,
+    }
+}
+
+async fn handle_config_diff(
+    ctx: &Context,
+    command: &CommandInteraction,
+    config: &Config,
+) -> Result<()> {
+    let on_disk = match Config::load() {
+        Ok(on_disk) => on_disk,
+        Err(error) => {
+            let content = format!("Failed to load the config file: {error}");
+            return reply_ephemeral(ctx, command, content).await;
+        }
+    };
+
+    let changes = config_diff(config, &on_disk)?;
+    let content = if changes.is_empty() {
+        String::from("The config file matches the running config")
+    } else {
+        format!(
+            "**{} changed fields**\n{}",
+            changes.len(),
+            capped_list(&changes, 20)
+        )
+    };
+
+    reply_ephemeral(ctx, command, content).await
+}
+
+/// Replies with the config file as it is on disk, so syntax errors that keep it from loading can
+/// be seen; the token is redacted.
+async fn handle_config_raw(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    let path = Config::path()?;
+    let raw = match fs::read_to_string(&path) {
+        Ok(raw) => raw,
+        Err(error) => {
+            let content = format!("Failed to read '{}': {error}", path.display());
+            return reply_ephemeral(ctx, command, content).await;
+        }
+    };
+
+    let redacted = redact_config(&raw);
+    let block = format!("```toml\n{redacted}\n```");
+    let message = if block.len() <= 2000 {
+        CreateInteractionResponseMessage::new().content(block)
+    } else {
+        CreateInteractionResponseMessage::new()
+            .content(format!("`{}` is too long to show inline", path.display()))
+            .add_file(CreateAttachment::bytes(
+                redacted.into_bytes(),
+                "config.toml",
+            ))
+    };
+
+    let response = CreateInteractionResponse::Message(message.ephemeral(true));
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
+
+/// Replaces the value of every `SECRET_FIELDS` key, keeping every other line as it is.
+fn redact_config(raw: &str) -> String {
+    raw.lines()
+        .map(|line| {
+            let secret = SECRET_FIELDS.iter().find(|field| {
+                line.trim_start()
+                    .strip_prefix(**field)
+                    .is_some_and(|rest| rest.trim_start().starts_with('='))
+            });
+            secret.map_or_else(
+                || line.to_string(),
+                |field| format!("{field} = \"<redacted>\""),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+async fn handle_theme_command(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    let Some(config) = get_config(ctx).await else {
+        return reply_ephemeral(ctx, command, "Configuration not found").await;
+    };
+
+    if command.guild_id != Some(config.guild) {
+        return reply_ephemeral(ctx, command, "This command is not available here").await;
+    }
+
+    if !is_staff(command) {
+        return reply_ephemeral(ctx, command, "You need the Manage Server permission").await;
+    }
+
+    match subcommand(command) {
+        Some("list") => {
+            let packs = config
+                .themes
+                .iter()
+                .map(|(name, pack)| format!("**{name}**: {}", pack.describe()))
+                .collect::<Vec<_>>();
+            let content = if packs.is_empty() {
+                String::from("No theme packs are configured")
+            } else {
+                capped_list(&packs, 20)
+            };
+
+            reply_ephemeral(ctx, command, content).await
+        }
+        Some("apply") => {
+            let Some(name) = string_option(command, "name") else {
+                return reply_ephemeral(ctx, command, "Please provide a theme pack name").await;
+            };
+
+            let Some(pack) = config.themes.get(name) else {
+                let content = format!("There is no theme pack named `{name}`");
+                return reply_ephemeral(ctx, command, content).await;
+            };
+
+            command.defer_ephemeral(&ctx.http).await?;
+            let content = apply_theme_pack(ctx, &config, name, pack).await?;
+            info!("[{}] applied theme pack '{name}'", command.user.name);
+            command
+                .edit_response(&ctx.http, EditInteractionResponse::new().content(content))
+                .await?;
+
+            Ok(())
+        }
+        _ => reply_ephemeral(ctx, command, "Unknown subcommand").await,
+    }
+}
+
+/// Applies every part of the pack that could be loaded in a single guild edit, reporting the
+/// parts that were skipped.
+async fn apply_theme_pack(
+    ctx: &Context,
+    config: &Config,
+    name: &str,
+    pack: &ThemePack,
+) -> Result<String> {
+    let mut skipped = Vec::new();
+
+    let mut icon = None;
+    if let Some(filename) = &pack.icon {
+        match find_icon(config, filename) {
+            Some(path) => match icon_attachment(&path, config.convert_icons).await {
+                Ok(attachment) => icon = Some((filename.clone(), attachment)),
+                Err(error) => skipped.push(format!("icon `{filename}`: {error}")),
+            },
+            None => skipped.push(format!("icon `{filename}`: not found")),
+        }
+    }
+
+    let mut banner = None;
+    if let Some(path) = &pack.banner {
+        let features = guild_features(ctx, config).await?;
+        if features.iter().any(|feature| feature == "BANNER") {
+            match CreateAttachment::path(path).await {
+                Ok(attachment) => banner = Some(attachment),
+                Err(error) => skipped.push(format!("banner `{}`: {error}", path.display())),
+            }
+        } else {
+            skipped.push(format!(
+                "banner `{}`: the server has no banner feature",
+                path.display()
+            ));
+        }
+    }
+
+    if icon.is_none() && banner.is_none() && pack.name.is_none() {
+        return Ok(format!(
+            "Nothing from theme pack `{name}` could be applied\n{}",
+            skipped.join("\n")
+        ));
+    }
+
+    let mut builder = EditGuild::new();
+    if let Some((_, attachment)) = &icon {
+        builder = builder.icon(Some(attachment));
+    }
+
+    if let Some(attachment) = &banner {
+        builder = builder.banner(Some(attachment.to_base64()));
+    }
+
+    if let Some(guild_name) = &pack.name {
+        builder = builder.name(guild_name);
+    }
+
+    let lock = icon_lock(ctx).await;
+    let _guard = lock.lock().await;
+
+    if icon.is_some() {
+        ctx.data
+            .write()
+            .await
+            .entry::<AppliedIcon>()
+            .or_default()
+            .uploading = true;
+    }
+
+    let result = config.guild.edit(&ctx.http, builder).await;
+    let mut data = ctx.data.write().await;
+    let applied_icon = data.entry::<AppliedIcon>().or_default();
+    applied_icon.uploading = false;
+    let guild = match result {
+        Ok(guild) => guild,
+        Err(error) => {
+            drop(data);
+            return Ok(format!("Discord rejected theme pack `{name}`: {error}"));
+        }
+    };
+
+    if let Some((filename, _)) = &icon {
+        applied_icon.filename = Some(filename.clone());
+        applied_icon.hash = guild.icon;
+    }
+    drop(data);
+
+    if let Some((filename, _)) = icon {
+        record_icon_history(ctx, filename).await;
+    }
+
+    let mut content = format!("Applied theme pack `{name}`");
+    if !skipped.is_empty() {
+        content.push_str("\nSkipped:\n");
+        content.push_str(&skipped.join("\n"));
+    }
+
+    Ok(content)
+}
+
+async fn handle_version_command(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    let version = env!("CARGO_PKG_VERSION");
+    let content = option_env!("GIT_COMMIT").map_or_else(
+        || format!("Running the-devs-bot v{version}"),
+        |commit| format!("Running the-devs-bot v{version} (commit `{commit}`)"),
+    );
+
+    reply_ephemeral(ctx, command, content).await
+}
+
+/// Whether the user owns the application, directly or as a member of the owning team.
+async fn is_application_owner(ctx: &Context, user_id: UserId) -> Result<bool> {
+    let info = ctx.http.get_current_application_info().await?;
+    let on_team = info
+        .team
+        .is_some_and(|team| team.members.iter().any(|member| member.user.id == user_id));
+
+    Ok(on_team || info.owner.is_some_and(|owner| owner.id == user_id))
+}
+
+/// How many guilds `/guilds` lists before summarizing the rest.
+const MAX_LISTED_GUILDS: usize = 50;
+
+/// Lists the cached guilds by member count, marking the configured one.
+async fn handle_guilds_command(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    if !is_application_owner(ctx, command.user.id).await? {
+        return reply_ephemeral(ctx, command, "Only the bot's owner can use this command").await;
+    }
+
+    let configured = get_config(ctx).await.map(|config| config.guild);
+    let mut guilds = ctx
+        .cache
+        .guilds()
+        .into_iter()
+        .map(|guild_id| {
+            let (name, members) = ctx.cache.guild(guild_id).map_or_else(
+                || (String::from("Unknown"), 0),
+                |guild| (guild.name.clone(), guild.member_count),
+            );
+            (guild_id, name, members)
+        })
+        .collect::<Vec<_>>();
+    guilds.sort_by(|(_, _, first), (_, _, second)| second.cmp(first));
+
+    let mut lines = Vec::new();
+    let mut length = 0;
+    for (guild_id, name, members) in guilds.iter().take(MAX_LISTED_GUILDS) {
+        let marker = if Some(*guild_id) == configured {
+            " (configured)"
+        } else {
+            ""
+        };
+        let line = format!("**{name}** `{guild_id}`: {members} members{marker}");
+        length += line.len() + 1;
+        if length > 4000 {
+            break;
+        }
+
+        lines.push(line);
+    }
+
+    if guilds.len() > lines.len() {
+        lines.push(format!("…and {} more", guilds.len() - lines.len()));
+    }
+
+    let embed = CreateEmbed::new()
+        .title(format!("In {} servers", guilds.len()))
+        .description(lines.join("\n"));
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .embed(embed)
+            .ephemeral(true),
+    );
+
+    command.create_response(&ctx.http, response).await?;
+    Ok(())
+}
+
+async fn handle_serverinfo_command(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    let Some(config) = get_config(ctx).await else {
+        return reply_ephemeral(ctx, command, "Configuration not found").await;
+    };
+
+    if command.guild_id != Some(config.guild) {
+        return reply_ephemeral(ctx, command, "This command is not available here").await;
+    }
+
+    if config.serverinfo_staff_only && !is_staff(command) {
+        return reply_ephemeral(ctx, command, "You need the Manage Server permission").await;
+    }
+
+    let guild = config.guild.to_partial_guild_with_counts(&ctx.http).await?;
+    let channels = config.guild.channels(&ctx.http).await?;
+    let feature = |name: &str| {
+        if guild.features.iter().any(|feature| feature == name) {
+            "available"
+        } else {
+            "unavailable"
+        }
+    };
+
+    let lines = [
+        format!("**{}**", guild.name),
+        format!(
+            "Members: {}",
+            guild
+                .approximate_member_count
+                .map_or_else(|| String::from("unknown"), |count| count.to_string())
+        ),
+        format!("Channels: {}", channels.len()),
+        format!("Roles: {}", guild.roles.len()),
+        format!(
+            "Boost level: {} ({} boosts)",
+            u8::from(guild.premium_tier),
+            guild.premium_subscription_count.unwrap_or_default()
+        ),
+        format!("Animated icon: {}", feature("ANIMATED_ICON")),
+        format!("Banner: {}", feature("BANNER")),
+    ];
+
+    reply_ephemeral(ctx, command, lines.join("\n")).await
+}
+
+async fn handle_video_command(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    let Some(config) = get_config(ctx).await else {
+        return reply_ephemeral(ctx, command, "Configuration not found").await;
+    };
+
+    if command.guild_id != Some(config.guild) {
+        return reply_ephemeral(ctx, command, "This command is not available here").await;
+    }
+
+    let user_id = command.user.id;
+    let content = match subcommand(command) {
+        Some("optout") => {
+            update_state(ctx, |state| {
+                state.video_opt_outs.insert(user_id);
+            })
+            .await?;
+            "You will no longer be moved to the video channel when you start streaming"
+        }
+        Some("optin") => {
+            update_state(ctx, |state| {
+                state.video_opt_outs.remove(&user_id);
+            })
+            .await?;
+            "You will be moved to the video channel when you start streaming"
+        }
+        _ => return reply_ephemeral(ctx, command, "Unknown subcommand").await,
+    };
+
+    info!("[{}] set their video preference", command.user.name);
+    reply_ephemeral(ctx, command, content).await
+}
+
+async fn handle_sync_command(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    let Some(config) = get_config(ctx).await else {
+        return reply_ephemeral(ctx, command, "Configuration not found").await;
+    };
+
+    if command.guild_id != Some(config.guild) {
+        return reply_ephemeral(ctx, command, "This command is not available here").await;
+    }
+
+    if !is_staff(command) {
+        return reply_ephemeral(ctx, command, "You need the Manage Server permission").await;
+    }
+
+    command.defer_ephemeral(&ctx.http).await?;
+
+    let content = match sync_commands(ctx).await {
+        Ok(commands) => {
+            let names = commands
+                .iter()
+                .map(|command| format!("/{}", command.name))
+                .collect::<Vec<_>>();
+
+            info!(
+                "[{}] re-registered {} commands",
+                command.user.name,
+                names.len()
+            );
+            format!("Registered {} commands: {}", names.len(), names.join(", "))
+        }
+        Err(error) => {
+            error!("Error re-registering commands: {error}");
+            String::from("Failed to re-register commands. Check the logs for details.")
+        }
+    };
+
+    command
+        .edit_response(&ctx.http, EditInteractionResponse::new().content(content))
+        .await?;
+
+    Ok(())
+}
+
+async fn handle_maintenance_command(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    let Some(config) = get_config(ctx).await else {
+        return reply_ephemeral(ctx, command, "Configuration not found").await;
+    };
+
+    if command.guild_id != Some(config.guild) {
+        return reply_ephemeral(ctx, command, "This command is not available here").await;
+    }
+
+    if !is_staff(command) {
+        return reply_ephemeral(ctx, command, "You need the Manage Server permission").await;
+    }
+
+    match string_option(command, "mode") {
+        Some("on") => run_or_confirm(ctx, command, &config, DestructiveAction::MaintenanceOn).await,
+        Some("off") => {
+            let mut data = ctx.data.write().await;
+            data.insert::<Maintenance>(false);
+            data.entry::<ScheduledMaintenance>()
+                .or_default()
+                .switched_on = false;
+            drop(data);
+
+            let content = "Maintenance mode disabled, automation resumed";
+            info!("[{}] {content}", command.user.name);
+            reply_ephemeral(ctx, command, content).await
+        }
+        _ => reply_ephemeral(ctx, command, "Please choose on or off").await,
+    }
+}
+
+/// Swaps the log filter, returning the revision a scheduled reset has to match.
+async fn set_log_filter(ctx: &Context, directives: Option<&str>) -> Result<u64> {
+    let mut data = ctx.data.write().await;
+    let Some(log_filter) = data.get_mut::<LogFilter>() else {
+        bail!("Logging is not reloadable");
+    };
+
+    let filter = EnvFilter::try_new(directives.unwrap_or(&log_filter.default))?;
+    log_filter.handle.reload(filter)?;
+    log_filter.revision += 1;
+    let revision = log_filter.revision;
+    drop(data);
+
+    Ok(revision)
+}
+
+/// Raises or lowers the bot's own log level, going back to the default filter after
+/// `log_level_reset_minutes` unless changed again in the meantime.
+async fn handle_loglevel_command(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    let Some(config) = get_config(ctx).await else {
+        return reply_ephemeral(ctx, command, "Configuration not found").await;
+    };
+
+    if command.guild_id != Some(config.guild) {
+        return reply_ephemeral(ctx, command, "This command is not available here").await;
+    }
+
+    if !is_staff(command) {
+        return reply_ephemeral(ctx, command, "You need the Manage Server permission").await;
+    }
+
+    let level = match string_option(command, "level") {
+        Some("default") => None,
+        Some(level @ ("trace" | "debug" | "info" | "warn" | "error")) => Some(level),
+        _ => return reply_ephemeral(ctx, command, "Please choose a log level").await,
+    };
+
+    /* Dependencies stay at warn so the bot's own logs remain readable */
+    let directives = level.map(|level| format!("warn,the_devs_bot={level}"));
+    let revision = set_log_filter(ctx, directives.as_deref()).await?;
+
+    let Some(level) = level else {
+        info!("[{}] restored the default log level", command.user.name);
+        return reply_ephemeral(ctx, command, "The default log level is back").await;
+    };
+
+    info!("[{}] set the log level to {level}", command.user.name);
+    let minutes = config.log_level_reset_minutes;
+    if minutes == 0 {
+        let content = format!("Logging at {level} until `/loglevel default`");
+        return reply_ephemeral(ctx, command, content).await;
+    }
+
+    let reset_ctx = ctx.clone();
+    tokio::spawn(async move {
+        sleep(Duration::from_secs(minutes.saturating_mul(60))).await;
+        let current = reset_ctx
+            .data
+            .read()
+            .await
+            .get::<LogFilter>()
+            .map(|log_filter| log_filter.revision);
+        if current != Some(revision) {
+            return;
+        }
+
+        match set_log_filter(&reset_ctx, None).await {
+            Ok(_) => info!("Restored the default log level after {minutes} minutes"),
+            Err(error) => error!("Error restoring the default log level: {error}"),
+        }
+    });
+
+    let content = format!("Logging at {level} for the next {minutes} minutes");
+    reply_ephemeral(ctx, command, content).await
+}
+
+async fn handle_stats_command(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    let Some(config) = get_config(ctx).await else {
+        return reply_ephemeral(ctx, command, "Configuration not found").await;
+    };
+
+    if command.guild_id != Some(config.guild) {
+        return reply_ephemeral(ctx, command, "This command is not available here").await;
+    }
+
+    if !is_staff(command) {
+        return reply_ephemeral(ctx, command, "You need the Manage Server permission").await;
+    }
+
+    let unused = load_icon_paths(&config.server_icons_unused, config.follow_symlinks)
+        .map_or(0, |paths| paths.len());
+    let used = load_icon_paths(&config.server_icons_used, config.follow_symlinks)
+        .map_or(0, |paths| paths.len());
+    let history = ctx
+        .data
+        .read()
+        .await
+        .get::<State>()
+        .map_or(0, |state| state.icon_history.len());
+
+    let retention = if config.history_retention_days == 0 {
+        String::from("unlimited")
+    } else {
+        format!("{} days", config.history_retention_days)
+    };
+
+    let breaker = ctx
+        .data
+        .read()
+        .await
+        .get::<IconBreaker>()
+        .copied()
+        .unwrap_or_default();
+
+    let breaker_state = breaker.describe(Instant::now());
+
+    let mut boosts = icon_weights(ctx)
+        .await
+        .boosts
+        .into_iter()
+        .map(|(name, multiplier)| format!("`{name}` x{multiplier}"))
+        .collect::<Vec<_>>();
+    boosts.sort();
+    let boosts = if boosts.is_empty() {
+        String::from("none")
+    } else {
+        boosts.join(", ")
+    };
+
+    let lines = [
+        String::from("**Icon statistics**"),
+        format!("Unused icons: {unused}"),
+        format!("Used icons: {used}"),
+        format!(
+            "History: {history}/{} entries (retention: {retention})",
+            config.history_max_entries
+        ),
+        format!("Circuit breaker: {breaker_state}"),
+        format!(
+            "Pinned icon: {}",
+            pinned_icon(ctx)
+                .await
+                .unwrap_or_else(|| String::from("none"))
+        ),
+        format!("Boosted icons: {boosts}"),
+        format!(
+            "Maintenance mode: {}",
+            if maintenance_active(ctx).await {
+                "on"
+            } else {
+                "off"
+            }
+        ),
+        format!(
+            "Scheduled maintenance window: {}",
+            if maintenance_window_active(ctx).await {
+                "active"
+            } else {
+                "inactive"
+            }
+        ),
+    ];
+
+    reply_ephemeral(ctx, command, lines.join("\n")).await
+}
+
+async fn handle_icon_command(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    let Some(config) = get_config(ctx).await else {
+        return reply_ephemeral(ctx, command, "Configuration not found").await;
+    };
+
+    if command.guild_id != Some(config.guild) {
+        return reply_ephemeral(ctx, command, "This command is not available here").await;
+    }
+
+    if !is_staff(command) {
+        return reply_ephemeral(ctx, command, "You need the Manage Server permission").await;
+    }
+
+    match subcommand(command) {
+        Some("preview") => handle_icon_preview(ctx, command, &config).await,
+        Some("unused") => handle_icon_unused(ctx, command, &config).await,
+        Some("remove") => {
+            let Some(filename) = string_option(command, "filename") else {
+                return reply_ephemeral(ctx, command, "Please provide an icon filename").await;
+            };
+
+            let action = DestructiveAction::RemoveIcon(filename.to_string());
+            run_or_confirm(ctx, command, &config, action).await
+        }
+        Some("pin") => handle_icon_pin(ctx, command, &config).await,
+        Some("unpin") => handle_icon_unpin(ctx, command).await,
+        Some("poll") => handle_icon_poll(ctx, command, &config).await,
+        Some("reroll") => handle_icon_reroll(ctx, command, &config).await,
+        Some("breaker") => handle_icon_breaker(ctx, command).await,
+        Some("debug") => handle_icon_debug(ctx, command).await,
+        Some("interval") => handle_icon_interval(ctx, command).await,
+        Some("backup") => handle_icon_backup(ctx, command, &config).await,
+        Some("restore") => handle_icon_restore(ctx, command, &config).await,
+        Some("boost") => handle_icon_boost(ctx, command, &config).await,
+        Some("validate") => handle_icon_validate(ctx, command, &config).await,
+        Some("import-urls") => handle_icon_import_urls(ctx, command, &config).await,
+        Some("delay-test") => handle_icon_delay_test(ctx, command, &config).await,
+        _ => reply_ephemeral(ctx, command, "Unknown subcommand").await,
+    }
+}
+
+async fn handle_icon_preview(
+    ctx: &Context,
+    command: &CommandInteraction,
+    config: &Config,
+) -> Result<()> {
+    let Some(filename) = string_option(command, "filename") else {
+        return reply_ephemeral(ctx, command, "Please provide an icon filename").await;
+    };
+
+    let Some(path) = find_icon(config, filename) else {
+        let content = format!("No icon named '{filename}' was found");
+        return reply_ephemeral(ctx, command, content).await;
+    };
+
+    let attachment = CreateAttachment::path(&path).await?;
+    let embed = CreateEmbed::new()
+        .title(format!("Icon preview: {filename}"))
+        .image(format!("attachment://{}", attachment.filename));
+
+    let public = bool_option(command, "public").unwrap_or(false);
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .embed(embed)
+            .add_file(attachment)
+            .ephemeral(!public),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    info!("[{}] previewed icon '{filename}'", command.user.name);
+    Ok(())
+}
+
+async fn handle_icon_pin(
+    ctx: &Context,
+    command: &CommandInteraction,
+    config: &Config,
+) -> Result<()> {
+    let Some(filename) = string_option(command, "filename") else {
+        return reply_ephemeral(ctx, command, "Please provide an icon filename").await;
+    };
+
+    let Some(path) = find_icon(config, filename) else {
+        let content = format!("No icon named '{filename}' was found");
+        return reply_ephemeral(ctx, command, content).await;
+    };
+
+    command.defer_ephemeral(&ctx.http).await?;
+
+    let icon = IconCandidate::load(path);
+    let attachment = icon_attachment(&icon.path, config.convert_icons).await?;
+    let lock = icon_lock(ctx).await;
+    let guard = lock.lock().await;
+    apply_server_icon(ctx, config, &icon, &attachment, IconSource::Pin).await?;
+    drop(guard);
+    update_state(ctx, |state| state.pinned_icon = Some(filename.to_string())).await?;
+
+    info!("[{}] pinned icon '{filename}'", command.user.name);
+    let content = format!("Pinned '{filename}', the rotation is paused until `/icon unpin`");
+    command
+        .edit_response(&ctx.http, EditInteractionResponse::new().content(content))
+        .await?;
+
+    Ok(())
+}
+
+async fn handle_icon_unpin(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    let Some(pinned) = pinned_icon(ctx).await else {
+        return reply_ephemeral(ctx, command, "No icon is pinned").await;
+    };
+
+    update_state(ctx, |state| state.pinned_icon = None).await?;
+
+    info!("[{}] unpinned icon '{pinned}'", command.user.name);
+    let content = format!("Unpinned '{pinned}', the rotation resumes at the next change");
+    reply_ephemeral(ctx, command, content).await
+}
+
+/// Most URLs a single `/icon import-urls` downloads.
+const MAX_IMPORT_URLS: usize = 20;
+
+/// Largest icon Discord accepts, and so the largest download worth keeping.
+const MAX_ICON_BYTES: usize = 10 * 1024 * 1024;
+
+async fn handle_icon_import_urls(
+    ctx: &Context,
+    command: &CommandInteraction,
+    config: &Config,
+) -> Result<()> {
+    /* A Git clone is reset by the next pull, and without moves the directory may be read-only */
+    if !config.moves_icons() {
+        let content = if config.icon_git_repo.is_some() {
+            "Icons come from `icon_git_repo`, add them to the repository instead"
+        } else {
+            "Importing needs `move_applied_icons`, the icon directories are treated as read-only"
+        };
+        return reply_ephemeral(ctx, command, content).await;
+    }
+
+    command.defer_ephemeral(&ctx.http).await?;
+
+    let mut text = string_option(command, "urls")
+        .unwrap_or_default()
+        .to_string();
+    if let Some(file) = attachment_option(command, "file") {
+        let timeout = Duration::from_secs(config.http_timeout_seconds.max(1));
+        let Ok(bytes) = tokio::time::timeout(timeout, file.download()).await else {
+            warn!(
+                "Downloading the URL list timed out after {} seconds",
+                timeout.as_secs()
+            );
+            let builder = EditInteractionResponse::new().content("Downloading the file timed out");
+            command.edit_response(&ctx.http, builder).await?;
+            return Ok(());
+        };
+
+        text.push('\n');
+        text.push_str(&String::from_utf8_lossy(&bytes?));
+    }
+
+    let urls = text.split_whitespace().collect::<Vec<_>>();
+    let content = if urls.is_empty() {
+        String::from("Please provide image URLs or a text file with one per line")
+    } else {
+        let client = http_client(config)?;
+
+        let mut failures = Vec::new();
+        let mut imported = 0;
+        for url in urls.iter().take(MAX_IMPORT_URLS) {
+            match import_icon_url(&client, url, &config.server_icons_unused).await {
+                Ok(filename) => {
+                    info!(
+                        "[{}] imported icon '{filename}' from {url}",
+                        command.user.name
+                    );
+                    imported += 1;
+                }
+                Err(error) if is_timeout(&error) => {
+                    warn!("Importing icon from {url} timed out");
+                    failures.push(format!("<{url}>: timed out"));
+                }
+                Err(error) => failures.push(format!("<{url}>: {error}")),
+            }
+        }
+
+        let skipped = urls.len().saturating_sub(MAX_IMPORT_URLS);
+        let mut content = format!("Imported {imported} icons, {} failed", failures.len());
+        if skipped > 0 {
+            content = format!(
+                "{content} ({skipped} URLs over the limit of {MAX_IMPORT_URLS} were skipped)"
+            );
+        }
+
+        if !failures.is_empty() {
+            content.push('\n');
+            content.push_str(&capped_list(&failures, 10));
+        }
+
+        content
+    };
+
+    command
+        .edit_response(&ctx.http, EditInteractionResponse::new().content(content))
+        .await?;
+
+    Ok(())
+}
+
+/// Downloads one image into the directory, keeping the filename from the URL and refusing to
+/// overwrite an existing icon.
+async fn import_icon_url(client: &reqwest::Client, url: &str, directory: &Path) -> Result<String> {
+    let parsed = reqwest::Url::parse(url)?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        bail!("only http and https URLs are supported");
+    }
+
+    let filename = parsed
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|segment| !segment.is_empty())
+        .ok_or_else(|| eyre!("the URL has no filename"))?
+        .to_string();
+
+    let path = directory.join(&filename);
+    if !is_supported_icon(&path) {
+        bail!("'{filename}' is not a supported image format");
+    }
+
+    if path.exists() {
+        bail!("'{filename}' already exists");
+    }
+
+    let mut response = client.get(parsed).send().await?.error_for_status()?;
+    let is_image = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("image/"));
+    if !is_image {
+        bail!("the server did not return an image");
+    }
+
+    let too_large = response
+        .content_length()
+        .is_some_and(|length| length > MAX_ICON_BYTES as u64);
+    if too_large {
+        bail!("the image is larger than 10 MiB");
+    }
+
+    /* The length header can be missing or wrong, so the body is capped as it arrives */
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if bytes.len() + chunk.len() > MAX_ICON_BYTES {
+            bail!("the image is larger than 10 MiB");
+        }
+
+        bytes.extend_from_slice(&chunk);
+    }
+
+    fs::write(&path, &bytes)?;
+    Ok(filename)
+}
+
+async fn handle_icon_boost(
+    ctx: &Context,
+    command: &CommandInteraction,
+    config: &Config,
+) -> Result<()> {
+    let (Some(filename), Some(multiplier), Some(hours)) = (
+        string_option(command, "filename"),
+        number_option(command, "multiplier"),
+        integer_option(command, "hours").and_then(|hours| u64::try_from(hours).ok()),
+    ) else {
+        return reply_ephemeral(
+            ctx,
+            command,
+            "Please provide a filename, multiplier and hours",
+        )
+        .await;
+    };
+
+    if find_icon(config, filename).is_none() {
+        let content = format!("No icon named '{filename}' was found");
+        return reply_ephemeral(ctx, command, content).await;
+    }
+
+    let expires_at = Instant::now() + Duration::from_hours(hours);
+    ctx.data
+        .write()
+        .await
+        .entry::<IconBoosts>()
+        .or_default()
+        .insert(filename.to_string(), (multiplier, expires_at));
+
+    info!(
+        "[{}] boosted icon '{filename}' x{multiplier} for {hours} hours",
+        command.user.name
+    );
+    let content = format!("Boosted `{filename}` x{multiplier} for {hours} hours");
+    reply_ephemeral(ctx, command, content).await
+}
+
+async fn handle_icon_validate(
+    ctx: &Context,
+    command: &CommandInteraction,
+    config: &Config,
+) -> Result<()> {
+    command.defer_ephemeral(&ctx.http).await?;
+
+    let mut checked = 0;
+    let mut report = Vec::new();
+    for directory in [&config.server_icons_unused, &config.server_icons_used] {
+        let Ok(entries) = fs::read_dir(directory) else {
+            continue;
+        };
+
+        for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+            let is_sidecar = path
+                .extension()
+                .is_some_and(|extension| extension == "meta");
+            if !path.is_file() || is_sidecar {
+                continue;
+            }
+
+            checked += 1;
+            let problems = icon_problems(&path, config);
+            if !problems.is_empty() {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                report.push(format!("`{name}`: {}", problems.join(", ")));
+            }
+        }
+    }
+
+    let mut lines = vec![if report.is_empty() {
+        format!("All {checked} icon files look fine")
+    } else {
+        format!(
+            "**{} of {checked} icon files have problems**\n{}",
+            report.len(),
+            capped_list(&report, 20)
+        )
+    }];
+
+    let mut rejected = ctx
+        .data
+        .read()
+        .await
+        .get::<State>()
+        .map(|state| state.rejected_icons.len())
+        .unwrap_or_default();
+    if rejected > 0 && bool_option(command, "retry_rejected").unwrap_or(false) {
+        update_state(ctx, |state| state.rejected_icons.clear()).await?;
+        info!(
+            "[{}] cleared {rejected} icons Discord rejected",
+            command.user.name
+        );
+        lines.push(format!(
+            "Forgot {rejected} icons Discord rejected, the rotation tries them again"
+        ));
+        rejected = 0;
+    }
+
+    if rejected > 0 {
+        lines.push(format!(
+            "{rejected} icons Discord rejected are skipped, use `retry_rejected` to try them again"
+        ));
+    }
+
+    let content = lines.join("\n");
+    command
+        .edit_response(&ctx.http, EditInteractionResponse::new().content(content))
+        .await?;
+
+    Ok(())
+}
+
+async fn handle_icon_reroll(
+    ctx: &Context,
+    command: &CommandInteraction,
+    config: &Config,
+) -> Result<()> {
+    let cooldown = Duration::from_secs(config.icon_reroll_cooldown_seconds);
+    let remaining = ctx
+        .data
+        .read()
+        .await
+        .get::<RerollCooldowns>()
+        .and_then(|cooldowns| cooldowns.get(&config.guild))
+        .and_then(|rerolled_at| cooldown.checked_sub(rerolled_at.elapsed()))
+        .filter(|remaining| !remaining.is_zero());
+
+    if let Some(remaining) = remaining {
+        let content = format!(
+            "The icon was rerolled recently, please wait {} more seconds",
+            remaining.as_secs() + 1
+        );
+        return reply_ephemeral(ctx, command, content).await;
+    }
+
+    if let Some(reason) = rotation_hold(ctx).await {
+        let content = format!("{reason}, not rerolling the icon");
+        return reply_ephemeral(ctx, command, content).await;
+    }
+
+    command.defer_ephemeral(&ctx.http).await?;
+
+    let previous = applied_icon_name(ctx).await;
+    let applied = randomize_server_icon(ctx).await?;
+    if let Some(icon_name) = &applied {
+        info!("[{}] rerolled the icon to '{icon_name}'", command.user.name);
+        ctx.data
+            .write()
+            .await
+            .entry::<RerollCooldowns>()
+            .or_default()
+            .insert(config.guild, Instant::now());
+    }
+
+    /* Rotating already recycled the used icons when it could, so an empty pool stays empty */
+    let pool_empty = load_icon_paths(&config.server_icons_unused, config.follow_symlinks)
+        .is_ok_and(|paths| paths.is_empty());
+    let content = match (applied, previous) {
+        (Some(icon_name), Some(previous)) => format!("Replaced `{previous}` with `{icon_name}`"),
+        (Some(icon_name), None) => format!("Applied `{icon_name}`"),
+        (None, _) if pool_empty => String::from(
+            "No icons are left in the unused directory, even after recycling the used ones",
+        ),
+        (None, _) => String::from("No icon could be applied, check the icon directories"),
+    };
+
+    command
+        .edit_response(&ctx.http, EditInteractionResponse::new().content(content))
+        .await?;
+
+    Ok(())
+}
+
+/// Downloads the guild's current icon into `icon_backup_dir`, named after the time of the backup.
+async fn handle_icon_backup(
+    ctx: &Context,
+    command: &CommandInteraction,
+    config: &Config,
+) -> Result<()> {
+    let Some(backup_dir) = &config.icon_backup_dir else {
+        return reply_ephemeral(ctx, command, "Set `icon_backup_dir` to enable icon backups").await;
+    };
+
+    command.defer_ephemeral(&ctx.http).await?;
+
+    let guild = config.guild.to_partial_guild(&ctx.http).await?;
+    let content = match guild.icon_url() {
+        None => String::from("The server has no icon to back up"),
+        Some(url) => {
+            let response = http_client(config)?
+                .get(&url)
+                .send()
+                .await?
+                .error_for_status()?;
+            let bytes = response.bytes().await?;
+
+            /* The CDN URL ends in the format Discord serves, e.g. `.gif` for animated icons */
+            let extension = Path::new(url.split('?').next().unwrap_or_default())
+                .extension()
+                .and_then(OsStr::to_str)
+                .unwrap_or("png");
+            let filename = format!(
+                "backup-{}.{extension}",
+                Timestamp::now().format("%Y%m%d-%H%M%S")
+            );
+
+            fs::create_dir_all(backup_dir)?;
+            fs::write(backup_dir.join(&filename), &bytes)?;
+            info!(
+                "[{}] backed up the server icon as '{filename}'",
+                command.user.name
+            );
+            format!("Saved the current icon as `{filename}`")
+        }
+    };
+
+    command
+        .edit_response(&ctx.http, EditInteractionResponse::new().content(content))
+        .await?;
+
+    Ok(())
+}
+
+/// Reapplies the newest file in `icon_backup_dir`, leaving it in place.
+async fn handle_icon_restore(
+    ctx: &Context,
+    command: &CommandInteraction,
+    config: &Config,
+) -> Result<()> {
+    let Some(backup_dir) = &config.icon_backup_dir else {
+        return reply_ephemeral(ctx, command, "Set `icon_backup_dir` to enable icon backups").await;
+    };
+
+    /* Backup names sort by the time they were taken */
+    let latest = load_icon_paths(backup_dir, true)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|path| icon_filename(path).is_ok_and(|name| name.starts_with("backup-")))
+        .max();
+    let Some(latest) = latest else {
+        return reply_ephemeral(ctx, command, "No icon backups were found").await;
+    };
+
+    command.defer_ephemeral(&ctx.http).await?;
+
+    let content = match apply_icon_file(ctx, &latest, Some(IconSource::Backup)).await {
+        Ok(icon_name) => {
+            info!(
+                "[{}] restored the icon backup '{icon_name}'",
+                command.user.name
+            );
+            format!("Restored `{icon_name}`")
+        }
+        Err(error) => format!("Restoring the backup failed: {error}"),
+    };
+
+    command
+        .edit_response(&ctx.http, EditInteractionResponse::new().content(content))
+        .await?;
+
+    Ok(())
+}
+
+/// Replies with the rotation state as JSON, attached as a file when it doesn't fit a message.
+async fn handle_icon_debug(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    let now = Instant::now();
+    let data = ctx.data.read().await;
+    let state = data.get::<State>().cloned().unwrap_or_default();
+    let applied = data.get::<AppliedIcon>().cloned().unwrap_or_default();
+    let breaker = data.get::<IconBreaker>().copied().unwrap_or_default();
+    let boosts = data
+        .get::<IconBoosts>()
+        .map(|boosts| {
+            boosts
+                .iter()
+                .filter(|(_, (_, expires_at))| *expires_at > now)
+                .map(|(name, (multiplier, expires_at))| {
+                    let remaining = expires_at.duration_since(now).as_secs();
+                    (name.clone(), serde_json::json!({ "multiplier": multiplier, "expires_in_secs": remaining }))
+                })
+                .collect::<serde_json::Map<_, _>>()
+        })
+        .unwrap_or_default();
+    let pending_approval = data
+        .get::<PendingIconApproval>()
+        .and_then(Option::as_ref)
+        .map(|approval| approval.path.display().to_string());
+    let history = state
+        .icon_history
+        .iter()
+        .rev()
+        .take(10)
+        .cloned()
+        .collect::<Vec<_>>();
+    let maintenance = data.get::<Maintenance>().copied().unwrap_or_default();
+    let calendar_event = data.get::<ActiveCalendarEvent>().cloned().flatten();
+    let flagged_theme = data.get::<FlagTheme>().cloned().flatten();
+    drop(data);
+
+    let dump = serde_json::json!({
+        "applied": {
+            "filename": applied.filename,
+            "hash": applied.hash.map(|hash| hash.to_string()),
+            "uploading": applied.uploading,
+        },
+        "pinned": state.pinned_icon,
+        "recent": state.recent_icons,
+        "rejected": state.rejected_icons,
+        "boosts": boosts,
+        "breaker": {
+            "failures": breaker.failures,
+            "paused_for_secs": breaker.remaining_pause(now).map(|remaining| remaining.as_secs()),
+        },
+        "next_change": state.next_icon_change,
+        "milestone_until": state.milestone_until,
+        "calendar_event": calendar_event,
+        "flag_theme": flagged_theme,
+        "poll": state.icon_poll.map(|poll| poll.options),
+        "icon_of_the_day": state.icon_of_the_day.map(|post| post.filename),
+        "pending_approval": pending_approval,
+        "maintenance": maintenance,
+        "recent_history": history,
+    });
+
+    let json = serde_json::to_string_pretty(&dump)?;
+    let block = format!("```json\n{json}\n```");
+    let message = if block.len() <= 2000 {
+        CreateInteractionResponseMessage::new().content(block)
+    } else {
+        CreateInteractionResponseMessage::new()
+            .content("The rotation state is too long to show inline")
+            .add_file(CreateAttachment::bytes(
+                json.into_bytes(),
+                "rotation-state.json",
+            ))
+    };
+
+    let response = CreateInteractionResponse::Message(message.ephemeral(true));
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
+
+async fn handle_icon_breaker(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    let mut data = ctx.data.write().await;
+    let breaker = data.entry::<IconBreaker>().or_default();
+    let reset = group_subcommand(command) == Some("reset");
+    let was_paused = breaker.remaining_pause(Instant::now()).is_some();
+    if reset {
+        breaker.record_success();
+    }
+
+    let state = breaker.describe(Instant::now());
+    drop(data);
+
+    /* Wakes the rotation loop, which is waiting out the pause */
+    if reset && was_paused {
+        icon_reschedule(ctx).await.notify_one();
+    }
+
+    let content = if reset {
+        info!("[{}] reset the circuit breaker", command.user.name);
+        format!("Circuit breaker reset, it is now {state}")
+    } else {
+        format!("Circuit breaker: {state}")
+    };
+
+    reply_ephemeral(ctx, command, content).await
+}
+
+/// Changes the delay range in the running config and the config file, then restarts the wait.
+async fn handle_icon_interval(ctx: &Context, command: &CommandInteraction) -> Result<()> {
+    let min_hours = integer_option(command, "min_hours")
+        .and_then(|hours| u64::try_from(hours).ok())
+        .unwrap_or_default();
+    let max_hours = integer_option(command, "max_hours")
+        .and_then(|hours| u64::try_from(hours).ok())
+        .unwrap_or_default();
+
+    /* Same checks the rotation loop applies, on a throwaway RNG */
+    let (min, max) = (
+        Duration::from_secs(min_hours.saturating_mul(3_600)),
+        Duration::from_secs(max_hours.saturating_mul(3_600)),
+    );
+    if let Err(error) = icon_delay(min, max, &mut StdRng::from_os_rng()) {
+        return reply_ephemeral(ctx, command, format!("Invalid delay range: {error}")).await;
+    }
+
+    /* The duration strings would take precedence over the new hours, so they are cleared */
+    let set_hours = |config: &mut Config| {
+        config.server_icons_delay_min_hours = min_hours;
+        config.server_icons_delay_max_hours = max_hours;
+        config.server_icons_delay_min = None;
+        config.server_icons_delay_max = None;
+    };
+
+    if let Some(config) = ctx.data.write().await.get_mut::<Config>() {
+        set_hours(config);
+    }
+
+    /* Only the delay fields change on disk, so anything passed on stdin stays off it */
+    let saved = Config::load().and_then(|mut on_disk| {
+        set_hours(&mut on_disk);
+        on_disk.save()
+    });
+
+    icon_reschedule(ctx).await.notify_one();
+    info!(
+        "[{}] set the icon delay range to {min_hours}-{max_hours} hours",
+        command.user.name
+    );
+
+    let content = match saved {
+        Ok(()) => format!("Icon changes now happen every {min_hours}-{max_hours} hours"),
+        Err(error) => format!(
+            "Icon changes now happen every {min_hours}-{max_hours} hours, but saving the config file failed: {error}"
+        ),
+    };
+
+    reply_ephemeral(ctx, command, content).await
+}
+
+/// Samples `icon_delay` with a separate RNG, so the live rotation sequence is left alone.
+async fn handle_icon_delay_test(
+    ctx: &Context,
+    command: &CommandInteraction,
+    config: &Config,
+) -> Result<()> {
+    let count = integer_option(command, "count")
+        .and_then(|count| usize::try_from(count).ok())
+        .unwrap_or(5)
+        .clamp(1, 20);
+
+    let range = match config.icon_delay_range() {
+        Ok(range) => range,
+        Err(error) => {
+            let content = format!("The icon delay is misconfigured: {error}");
+            return reply_ephemeral(ctx, command, content).await;
+        }
+    };
+
+    let mut rng = new_icon_rng(config);
+    let mut samples = Vec::new();
+    for _ in 0..count {
+        match icon_delay(range.0, range.1, &mut rng) {
+            Ok(Some(delay)) => samples.push(delay),
+            Ok(None) => return reply_ephemeral(ctx, command, "The icon delay is disabled").await,
+            Err(error) => {
+                let content = format!("The icon delay is misconfigured: {error}");
+                return reply_ephemeral(ctx, command, content).await;
+            }
+        }
+    }
+
+    let format_delay = |delay: Duration| {
+        let minutes = delay.as_secs() / 60;
+        format!("{}h {:02}m", minutes / 60, minutes % 60)
+    };
+
+    let lines = samples
+        .iter()
+        .enumerate()
+        .map(|(index, delay)| format!("{}. {}", index + 1, format_delay(*delay)))
+        .collect::<Vec<_>>();
+
+    let content = format!(
+        "**{count} sampled delays** (range {})\n{}\nShortest: {}, longest: {}",
+        describe_delay_range(range),
+        lines.join("\n"),
+        samples
+            .iter()
+            .min()
+            .copied()
+            .map(format_delay)
+            .unwrap_or_default(),
+        samples
+            .iter()
+            .max()
+            .copied()
+            .map(format_delay)
+            .unwrap_or_default(),
+    );
+
+    reply_ephemeral(ctx, command, content).await
+}
+
+/// Discord allows ten embeds per message, one per poll option's thumbnail.
+const MAX_POLL_OPTIONS: usize = 10;
+
+async fn handle_icon_poll(
+    ctx: &Context,
+    command: &CommandInteraction,
+    config: &Config,
+) -> Result<()> {
+    let count = integer_option(command, "count")
+        .and_then(|count| usize::try_from(count).ok())
+        .unwrap_or(4)
+        .clamp(2, MAX_POLL_OPTIONS);
+
+    let weights = icon_weights(ctx).await;
+    let mut candidates = list_icon_candidates(config)?;
+    let mut options = Vec::new();
+    while options.len() < count {
+        let Some(selected) = with_rng(ctx, |rng| {
+            select_icon(&candidates, config, &weights, SystemTime::now(), rng)
+        })
+        .await
+        else {
+            break;
+        };
+
+        candidates.retain(|candidate| candidate.path != selected.path);
+        match icon_attachment(&selected.path, config.convert_icons).await {
+            Ok(attachment) => options.push((selected, attachment)),
+            Err(error) => warn!(
+                "Leaving '{}' out of the icon poll: {error}",
+                selected.path.display()
+            ),
+        }
+    }
+
+    if options.len() < 2 {
+        return reply_ephemeral(
+            ctx,
+            command,
+            "At least two eligible icons are needed for a poll",
+        )
+        .await;
+    }
+
+    let mut message = CreateInteractionResponseMessage::new();
+    let mut answers = Vec::new();
+    let mut names = Vec::new();
+    for (index, (icon, attachment)) in options.into_iter().enumerate() {
+        let name = icon_filename(&icon.path)?;
+        let embed = CreateEmbed::new()
+            .title(format!("{}. {name}", index + 1))
+            .thumbnail(format!("attachment://{}", attachment.filename));
+
+        message = message.embed(embed).add_file(attachment);
+        answers.push(CreatePollAnswer::new().text(format!("{}. {name}", index + 1)));
+        names.push(name);
+    }
+
+    let answer_count = answers.len();
+    let hours = config.icon_poll_hours.clamp(1, 168);
+    let poll = CreatePoll::new()
+        .question("Which icon should the server get next?")
+        .answers(answers)
+        .duration(Duration::from_secs(hours * 3600));
+
+    command
+        .create_response(
+            &ctx.http,
+            CreateInteractionResponse::Message(message.poll(poll)),
+        )
+        .await?;
+
+    let response = command.get_response(&ctx.http).await?;
+    let icon_poll = IconPoll {
+        channel_id: response.channel_id,
+        message_id: response.id,
+        options:    names,
+    };
+    update_state(ctx, |state| state.icon_poll = Some(icon_poll)).await?;
+
+    info!(
+        "[{}] started an icon poll with {} options",
+        command.user.name, answer_count
+    );
+    Ok(())
+}
+
+/// Takes the winner of the icon poll once Discord has finalized it, clearing the poll.
+async fn take_icon_poll_winner(ctx: &Context) -> Option<String> {
+    let icon_poll = ctx
+        .data
+        .read()
+        .await
+        .get::<State>()
+        .and_then(|state| state.icon_poll.clone())?;
+
+    let message = match icon_poll
+        .channel_id
+        .message(&ctx.http, icon_poll.message_id)
+        .await
+    {
+        Ok(message) => message,
+        Err(error) => {
+            warn!("Discarding the icon poll, its message could not be fetched: {error}");
+            if let Err(error) = update_state(ctx, |state| state.icon_poll = None).await {
+                error!("Error saving state: {error}");
+            }
+
+            return None;
+        }
+    };
+
+    let poll = message.poll?;
+    let results = poll.results.filter(|results| results.is_finalized)?;
+
+    if let Err(error) = update_state(ctx, |state| state.icon_poll = None).await {
+        error!("Error saving state: {error}");
+    }
+
+    let winner = results
+        .answer_counts
+        .iter()
+        .filter(|count| count.count > 0)
+        .max_by_key(|count| count.count)?;
+
+    let index = poll
+        .answers
+        .iter()
+        .position(|answer| answer.answer_id == winner.id)?;
+
+    icon_poll.options.get(index).cloned()
+}
+
+/// Lists the unused icons, or the used ones the next rotation would recycle when the pool is
+/// empty, without moving anything.
+fn list_icon_candidates(config: &Config) -> Result<Vec<IconCandidate>> {
+    let mut icon_paths = load_icon_paths(&config.server_icons_unused, config.follow_symlinks)?;
+    if icon_paths.is_empty() && config.moves_icons() {
+        icon_paths = load_icon_paths(&config.server_icons_used, config.follow_symlinks)?;
+    }
+
+    Ok(icon_paths.into_iter().map(IconCandidate::load).collect())
+}
+
+/// Loads the unused icons, recycling the used ones when the pool is empty and icons move.
+fn load_icon_candidates(
+    config: &Config,
+    last_shown: &BTreeMap<String, Timestamp>,
+) -> Result<Vec<IconCandidate>> {
+    let unused_dir = &config.server_icons_unused;
+    let used_dir = &config.server_icons_used;
+
+    let mut icon_paths = match load_icon_paths(unused_dir, config.follow_symlinks) {
+        Ok(paths) => paths,
+        Err(error) => {
+            let io_denied = error
+                .downcast_ref::<io::Error>()
+                .is_some_and(|io_error| io_error.kind() == io::ErrorKind::PermissionDenied);
+
+            if io_denied {
+                error!(
+                    "Server icon path '{}' is not readable: {error}",
+                    unused_dir.display()
+                );
+                return Ok(Vec::new());
+            }
+
+            return Err(error);
+        }
+    };
+
+    /* Without moves the used directory stays untouched, it may be read-only or a Git clone */
+    if icon_paths.is_empty() && config.moves_icons() {
+        info!(
+            "Server icon directory '{}' is empty, recycling used icons from '{}'",
+            unused_dir.display(),
+            used_dir.display()
+        );
+
+        icon_paths = match recycle_used_icons(
+            unused_dir,
+            used_dir,
+            config.recycle_batch_size,
+            config.follow_symlinks,
+            last_shown,
+        ) {
+            Ok(paths) => paths,
+            Err(error) => {
+                let io_denied = error
+                    .downcast_ref::<io::Error>()
+                    .is_some_and(|io_error| io_error.kind() == io::ErrorKind::PermissionDenied);
+
+                if io_denied {
+                    error!(
+                        "Server icon path '{}' is not readable: {error}",
+                        used_dir.display()
+                    );
+                    return Ok(Vec::new());
+                }
+
+                return Err(error);
+            }
+        };
+    }
+
+    if icon_paths.is_empty() {
+        if let Some(fallback_dir) = &config.server_icons_fallback {
+            info!(
+                "No icons to recycle, selecting from the fallback directory '{}'",
+                fallback_dir.display()
+            );
+            icon_paths = load_icon_paths(fallback_dir, config.follow_symlinks)?;
+        }
+    }
+
+    Ok(icon_paths.into_iter().map(IconCandidate::load).collect())
+}
+
+/// Whether the icon comes from `server_icons_fallback`, which is never consumed.
+fn is_fallback_icon(config: &Config, path: &Path) -> bool {
+    config
+        .server_icons_fallback
+        .as_deref()
+        .is_some_and(|fallback_dir| path.starts_with(fallback_dir))
+}
+
+async fn handle_icon_unused(
+    ctx: &Context,
+    command: &CommandInteraction,
+    config: &Config,
+) -> Result<()> {
+    let mut names = load_icon_paths(&config.server_icons_unused, config.follow_symlinks)?
+        .iter()
+        .filter_map(|path| icon_filename(path).ok())
+        .collect::<Vec<_>>();
+    names.sort();
+
+    if names.is_empty() {
+        return reply_ephemeral(ctx, command, "There are no unused icons").await;
+    }
+
+    let applied = ctx
+        .data
+        .read()
+        .await
+        .get::<State>()
+        .map(|state| {
+            state
+                .icon_history
+                .iter()
+                .map(|entry| entry.filename.clone())
+                .collect::<HashSet<_>>()
+        })
+        .unwrap_or_default();
+
+    let never_applied = names.iter().filter(|name| !applied.contains(*name)).count();
+    let items = names
+        .iter()
+        .map(|name| {
+            if applied.contains(name) {
+                format!("`{name}`")
+            } else {
+                format!("`{name}` (never applied)")
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let content = format!(
+        "**{} unused icons** ({never_applied} never applied according to the history)\n{}",
+        names.len(),
+        capped_list(&items, 20)
+    );
+
+    reply_ephemeral(ctx, command, content).await
+}
+
+/// Joins the items one per line, listing at most `max` of them followed by "... and N more".
+fn capped_list(items: &[String], max: usize) -> String {
+    let mut lines = items.iter().take(max).cloned().collect::<Vec<_>>();
+    if items.len() > max {
+        lines.push(format!("... and {} more", items.len() - max));
+    }
+
+    lines.join("\n")
+}
+
+/// Runs the action immediately, or asks the invoker to confirm it first when
+/// `confirm_destructive_actions` is enabled.
+async fn run_or_confirm(
+    ctx: &Context,
+    command: &CommandInteraction,
+    config: &Config,
+    action: DestructiveAction,
+) -> Result<()> {
+    if !config.confirm_destructive_actions {
+        let content = run_destructive_action(ctx, config, &action).await?;
+        info!("[{}] {content}", command.user.name);
+        return reply_ephemeral(ctx, command, content).await;
+    }
+
+    let id = command.id.to_string();
+    let buttons = vec![
+        CreateButton::new(format!("confirm:{id}"))
+            .label("Confirm")
+            .style(ButtonStyle::Danger),
+        CreateButton::new(format!("cancel:{id}"))
+            .label("Cancel")
+            .style(ButtonStyle::Secondary),
+    ];
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(format!("Are you sure you want to {}?", action.describe()))
+            .components(vec![CreateActionRow::Buttons(buttons)])
+            .ephemeral(true),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    let mut data = ctx.data.write().await;
+    let pending = data.entry::<PendingConfirmations>().or_default();
+    pending.retain(|_, confirmation| confirmation.created_at.elapsed() < CONFIRMATION_TIMEOUT);
+    pending.insert(
+        id,
+        PendingConfirmation {
+            user_id: command.user.id,
+            action,
+            created_at: Instant::now(),
+        },
+    );
+    drop(data);
+
+    Ok(())
+}
+
+async fn handle_component(ctx: &Context, component: &ComponentInteraction) -> Result<()> {
+    let Some((choice, id)) = component.data.custom_id.split_once(':') else {
+        return Ok(());
+    };
+
+    if matches!(choice, "icon-approve" | "icon-reject") {
+        return handle_icon_approval(ctx, component, choice, id).await;
+    }
+
+    if !matches!(choice, "confirm" | "cancel") {
+        return Ok(());
+    }
+
+    let pending = ctx
+        .data
+        .read()
+        .await
+        .get::<PendingConfirmations>()
+        .and_then(|pending| pending.get(id))
+        .map(|confirmation| (confirmation.user_id, confirmation.created_at));
+
+    let content = match pending {
+        None => String::from("This confirmation has expired"),
+        Some((user_id, _)) if user_id != component.user.id => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("Only the staff member who ran the command can confirm it")
+                    .ephemeral(true),
+            );
+            component.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+        Some((_, created_at)) if created_at.elapsed() >= CONFIRMATION_TIMEOUT => {
+            String::from("This confirmation has expired")
+        }
+        Some(_) => {
+            let confirmation = ctx
+                .data
+                .write()
+                .await
+                .get_mut::<PendingConfirmations>()
+                .and_then(|pending| pending.remove(id));
+
+            match (choice, confirmation, get_config(ctx).await) {
+                ("confirm", Some(confirmation), Some(config)) => {
+                    let content =
+                        run_destructive_action(ctx, &config, &confirmation.action).await?;
+                    info!("[{}] {content}", component.user.name);
+                    content
+                }
+                ("confirm", ..) => String::from("This confirmation has expired"),
+                _ => String::from("Cancelled"),
+            }
+        }
+    };
+
+    let response = CreateInteractionResponse::UpdateMessage(
+        CreateInteractionResponseMessage::new()
+            .content(content)
+            .components(Vec::new()),
+    );
+    component.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
+
+async fn run_destructive_action(
+    ctx: &Context,
+    config: &Config,
+    action: &DestructiveAction,
+) -> Result<String> {
+    match action {
+        DestructiveAction::RemoveIcon(filename) => {
+            let Some(path) = find_icon(config, filename) else {
+                return Ok(format!("No icon named '{filename}' was found"));
+            };
+
+            fs::remove_file(&path)?;
+            let sidecar = icon_meta_path(&path);
+            if sidecar.is_file() {
+                fs::remove_file(sidecar)?;
+            }
+
+            Ok(format!("Removed icon '{filename}'"))
+        }
+        DestructiveAction::MaintenanceOn => {
+            let mut data = ctx.data.write().await;
+            data.insert::<Maintenance>(true);
+            data.entry::<ScheduledMaintenance>()
+                .or_default()
+                .switched_on = false;
+            drop(data);
+            Ok(String::from(
+                "Maintenance mode enabled, automation is paused",
+            ))
+        }
+    }
+}
+
+/// The configured guild's features (e.g. `ANIMATED_ICON`, `BANNER`), kept current from the
+/// guild create and update events as boosts come and go.
+struct GuildFeatures;
+
+impl TypeMapKey for GuildFeatures {
+    type Value = Vec<String>;
+}
+
+/// The cached guild features, fetched from Discord when no event has provided them yet.
+async fn guild_features(ctx: &Context, config: &Config) -> Result<Vec<String>> {
+    if let Some(features) = ctx.data.read().await.get::<GuildFeatures>() {
+        return Ok(features.clone());
+    }
+
+    let features = config.guild.to_partial_guild(&ctx.http).await?.features;
+    ctx.data
+        .write()
+        .await
+        .insert::<GuildFeatures>(features.clone());
+    Ok(features)
+}
+
+/// The icon the bot applied last and the hash Discord gave it, so changes made outside the bot
+/// can be told apart from its own.
+#[derive(Clone, Default)]
+struct AppliedIcon {
+    filename:  Option<String>,
+    hash:      Option<ImageHash>,
+    /// Set while the bot's own upload is in flight, whose update can race its response.
+    uploading: bool,
+}
+
+impl TypeMapKey for AppliedIcon {
+    type Value = Self;
+}
+
+/// Filename of the icon currently shown, unless it was changed outside the bot since.
+async fn applied_icon_name(ctx: &Context) -> Option<String> {
+    ctx.data
+        .read()
+        .await
+        .get::<AppliedIcon>()
+        .and_then(|applied| applied.filename.clone())
+}
+
+/// Forgets the applied icon when the guild icon no longer matches the one the bot uploaded.
+async fn track_external_icon_change(ctx: &Context, config: &Config, guild: &PartialGuild) {
+    let mut data = ctx.data.write().await;
+    let Some(applied) = data.get_mut::<AppliedIcon>() else {
+        /* Nothing applied yet this session, just remember the current icon */
+        data.insert::<AppliedIcon>(AppliedIcon {
+            hash: guild.icon,
+            ..AppliedIcon::default()
+        });
+        return;
+    };
+
+    if applied.uploading || applied.hash == guild.icon {
+        return;
+    }
+
+    let previous = applied.filename.take();
+    applied.hash = guild.icon;
+    drop(data);
+
+    info!(
+        "Server icon was changed manually (bot last applied '{}')",
+        previous.as_deref().unwrap_or("nothing")
+    );
+    notify_staff(ctx, config, "Server icon was changed manually").await;
+}
+
+/// Serializes icon rotations, so a manual reroll and the scheduled loop never pick and move
+/// the same files at once.
+struct IconLock;
+
+impl TypeMapKey for IconLock {
+    type Value = Arc<tokio::sync::Mutex<()>>;
+}
+
+async fn icon_lock(ctx: &Context) -> Arc<tokio::sync::Mutex<()>> {
+    ctx.data
+        .write()
+        .await
+        .entry::<IconLock>()
+        .or_default()
+        .clone()
+}
+
+/// Applies a random icon from the pool, returning its filename when one was applied.
+async fn randomize_server_icon(ctx: &Context) -> Result<Option<String>> {
+    let lock = icon_lock(ctx).await;
+    let _guard = lock.lock().await;
+    randomize_server_icon_locked(ctx).await
+}
+
+async fn randomize_server_icon_locked(ctx: &Context) -> Result<Option<String>> {
+    let Some(config) = get_config(ctx).await else {
+        return Ok(None);
+    };
+
+    if config.server_icons_unused.as_os_str().is_empty() {
+        return Ok(None);
+    }
+
+    if let Some(winner) = take_icon_poll_winner(ctx).await {
+        if let Some(path) = find_icon(&config, &winner) {
+            info!("Applying '{winner}', the winner of the icon poll");
+            let icon = IconCandidate::load(path);
+            let attachment = icon_attachment(&icon.path, config.convert_icons).await?;
+            let icon_name =
+                apply_server_icon(ctx, &config, &icon, &attachment, IconSource::Rotation).await?;
+            return Ok(Some(icon_name));
+        }
+
+        warn!("The icon poll winner '{winner}' no longer exists");
+    }
+
+    let mut rejected = Vec::new();
+    for attempt in 1..=config.icon_upload_attempts.max(1) {
+        let Some((selected_icon, attachment)) = pick_server_icon(ctx, &config, &rejected).await?
+        else {
+            return Ok(None);
+        };
+
+        let source = IconSource::picked(&config, &selected_icon.path);
+        match apply_server_icon(ctx, &config, &selected_icon, &attachment, source).await {
+            Ok(icon_name) => return Ok(Some(icon_name)),
+            Err(error) if is_icon_rejection(&error) => {
+                let icon_name = icon_filename(&selected_icon.path)?;
+                warn!(
+                    "Discord rejected server icon '{icon_name}' (attempt {attempt} of {}), skipping it from now on: {error}",
+                    config.icon_upload_attempts.max(1)
+                );
+
+                if let Err(error) = update_state(ctx, |state| {
+                    state.rejected_icons.insert(icon_name);
+                })
+                .await
+                {
+                    error!("Error saving state: {error}");
+                }
+
+                rejected.push(selected_icon.path);
+            }
+            Err(error) => return Err(error),
+        }
+    }
+
+    warn!("Every server icon tried this rotation was rejected, giving up until the next one");
+    Ok(None)
+}
+
+/// Whether Discord refused the image itself (400 Bad Request), rather than the request failing.
+fn is_icon_rejection(error: &Report) -> bool {
+    matches!(
+        error.downcast_ref::<SerenityError>(),
+        Some(SerenityError::Http(HttpError::UnsuccessfulRequest(response)))
+            if response.status_code == StatusCode::BAD_REQUEST
+    )
+}
+
+/// An icon posted to the staging channel, waiting for a staff member to approve it.
+struct IconApproval {
+    id:       String,
+    path:     PathBuf,
+    rejected: Vec<PathBuf>,
+}
+
+struct PendingIconApproval;
+
+impl TypeMapKey for PendingIconApproval {
+    type Value = Option<IconApproval>;
+}
+
+/// Why the scheduled rotation has to leave the current icon alone, if it does.
+async fn rotation_hold(ctx: &Context) -> Option<String> {
+    if maintenance_active(ctx).await {
+        return Some(String::from("Maintenance mode is active"));
+    }
+
+    if let Some(pinned) = pinned_icon(ctx).await {
+        return Some(format!("Server icon '{pinned}' is pinned"));
+    }
+
+    if milestone_icon_active(ctx).await {
+        return Some(String::from("A member milestone icon is active"));
+    }
+
+    if let Some(event) = active_calendar_event(ctx).await {
+        return Some(format!("Calendar event '{event}' is active"));
+    }
+
+    None
+}
+
+/// The scheduled rotation: applies a random icon, or stages one for approval when
+/// `icon_requires_approval` is enabled.
+async fn rotate_server_icon(ctx: &Context) -> Result<()> {
+    let Some(config) = get_config(ctx).await else {
+        return Ok(());
+    };
+
+    let staging = config.approval_channel.or(config.log_channel);
+    let Some(channel_id) = staging.filter(|_| config.icon_requires_approval) else {
+        if config.icon_requires_approval {
+            warn!("Icon approval needs an approval or log channel, applying directly");
+        }
+
+        return randomize_server_icon(ctx).await.map(|_| ());
+    };
+
+    let pending = ctx
+        .data
+        .read()
+        .await
+        .get::<PendingIconApproval>()
+        .is_some_and(Option::is_some);
+    if pending {
+        info!("An icon is still waiting for approval, not staging another");
+        return Ok(());
+    }
+
+    propose_server_icon(ctx, &config, channel_id, Vec::new()).await
+}
+
+/// Posts the icon poll winner or a random candidate with Approve and Reject buttons,
+/// auto-applying it after `approval_timeout_minutes` when set and nothing holds the rotation.
+async fn propose_server_icon(
+    ctx: &Context,
+    config: &Config,
+    channel_id: ChannelId,
+    rejected: Vec<PathBuf>,
+) -> Result<()> {
+    let mut staged = None;
+    if let Some(winner) = take_icon_poll_winner(ctx).await {
+        if let Some(path) = find_icon(config, &winner) {
+            info!("Staging '{winner}', the winner of the icon poll");
+            let icon = IconCandidate::load(path);
+            let attachment = icon_attachment(&icon.path, config.convert_icons).await?;
+            staged = Some((icon, attachment));
+        } else {
+            warn!("The icon poll winner '{winner}' no longer exists");
+        }
+    }
+
+    if staged.is_none() {
+        staged = pick_server_icon(ctx, config, &rejected).await?;
+    }
+
+    let Some((icon, attachment)) = staged else {
+        ctx.data.write().await.insert::<PendingIconApproval>(None);
+        notify_staff(ctx, config, "No other icon candidates are left to approve").await;
+        return Ok(());
+    };
+
+    let icon_name = icon_filename(&icon.path)?;
+    let id = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos().to_string())
+        .unwrap_or_default();
+
+    let mut embed = CreateEmbed::new()
+        .title("Next server icon")
+        .description(format!("`{icon_name}` is up next, approve it to apply it"))
+        .thumbnail(format!("attachment://{}", attachment.filename))
+        .timestamp(Timestamp::now());
+
+    if config.approval_timeout_minutes > 0 {
+        embed = embed.footer(CreateEmbedFooter::new(format!(
+            "Applied automatically in {} minutes unless rejected",
+            config.approval_timeout_minutes
+        )));
+    }
+
+    let buttons = CreateActionRow::Buttons(vec![
+        CreateButton::new(format!("icon-approve:{id}"))
+            .label("Approve")
+            .style(ButtonStyle::Success),
+        CreateButton::new(format!("icon-reject:{id}"))
+            .label("Reject")
+            .style(ButtonStyle::Danger),
+    ]);
+
+    let message = CreateMessage::new()
+        .embed(embed)
+        .add_file(attachment)
+        .components(vec![buttons]);
+    channel_id.send_message(&ctx.http, message).await?;
+
+    info!("Staged server icon '{icon_name}' for approval");
+    ctx.data
+        .write()
+        .await
+        .insert::<PendingIconApproval>(Some(IconApproval {
+            id: id.clone(),
+            path: icon.path,
+            rejected,
+        }));
+
+    if config.approval_timeout_minutes > 0 {
+        let ctx = ctx.clone();
+        let timeout = Duration::from_secs(config.approval_timeout_minutes.saturating_mul(60));
+        tokio::spawn(async move {
+            sleep(timeout).await;
+            if let Some(approval) = take_icon_approval(&ctx, &id).await {
+                if let Some(reason) = rotation_hold(&ctx).await {
+                    info!("{reason}, discarding the unreviewed staged icon");
+                    return;
+                }
+
+                if icon_breaker_paused(&ctx).await {
+                    warn!("Server icon rotation is paused by the circuit breaker, discarding the unreviewed staged icon");
+                    return;
+                }
+
+                info!("Nobody reviewed the staged icon, applying it");
+                if let Err(error) = apply_icon_file(&ctx, &approval.path, None).await {
+                    error!("Error applying the staged server icon: {error}");
+                }
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Takes the pending approval if it is still the one with this ID.
+async fn take_icon_approval(ctx: &Context, id: &str) -> Option<IconApproval> {
+    let mut data = ctx.data.write().await;
+    let pending = data.get_mut::<PendingIconApproval>()?;
+    let taken = pending.take_if(|approval| approval.id == id);
+    drop(data);
+
+    taken
+}
+
+/// Applies a specific icon file under the icon lock, with the source the rotation would use
+/// unless one is given.
+async fn apply_icon_file(ctx: &Context, path: &Path, source: Option<IconSource>) -> Result<String> {
+    let Some(config) = get_config(ctx).await else {
+        bail!("Configuration not found");
+    };
+
+    let lock = icon_lock(ctx).await;
+    let _guard = lock.lock().await;
+
+    if !path.exists() {
+        bail!("'{}' no longer exists", path.display());
+    }
+
+    let icon = IconCandidate::load(path.to_path_buf());
+    let attachment = icon_attachment(&icon.path, config.convert_icons).await?;
+    let source = source.unwrap_or_else(|| IconSource::picked(&config, &icon.path));
+    apply_server_icon(ctx, &config, &icon, &attachment, source).await
+}
+
+/// Handles the Approve and Reject buttons of a staged icon.
+async fn handle_icon_approval(
+    ctx: &Context,
+    component: &ComponentInteraction,
+    choice: &str,
+    id: &str,
+) -> Result<()> {
+    let is_staff = component
+        .member
+        .as_ref()
+        .and_then(|member| member.permissions)
+        .is_some_and(Permissions::manage_guild);
+    if !is_staff {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You need the Manage Server permission")
+                .ephemeral(true),
+        );
+        component.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    let Some(mut approval) = take_icon_approval(ctx, id).await else {
+        let response = CreateInteractionResponse::UpdateMessage(
+            CreateInteractionResponseMessage::new()
+                .content("This icon was already reviewed")
+                .components(Vec::new()),
+        );
+        component.create_response(&ctx.http, response).await?;
+        return Ok(());
+    };
+
+    component.defer(&ctx.http).await?;
+    let name = component.user.name.clone();
+    let content = if choice == "icon-approve" {
+        match apply_icon_file(ctx, &approval.path, None).await {
+            Ok(icon_name) => format!("Approved by {name}, applied `{icon_name}`"),
+            Err(error) => format!("Approved by {name}, but applying failed: {error}"),
+        }
+    } else {
+        let channel_id = component.channel_id;
+        approval.rejected.push(approval.path.clone());
+        if let Some(config) = get_config(ctx).await {
+            propose_server_icon(ctx, &config, channel_id, approval.rejected).await?;
+        }
+
+        format!("Rejected by {name}")
+    };
+
+    info!("[{name}] reviewed the staged icon: {content}");
+    let builder = EditInteractionResponse::new()
+        .content(content)
+        .components(Vec::new());
+    component.edit_response(&ctx.http, builder).await?;
+
+    Ok(())
+}
+
+/// Picks a random icon from the pool that can be read for upload, skipping the excluded paths.
+async fn pick_server_icon(
+    ctx: &Context,
+    config: &Config,
+    excluded: &[PathBuf],
+) -> Result<Option<(IconCandidate, CreateAttachment)>> {
+    let unused_dir = &config.server_icons_unused;
+    let weights = icon_weights(ctx).await;
+    let mut candidates = load_icon_candidates(config, &weights.last_shown)?;
+    debug!("Selecting from {} icon candidates", candidates.len());
+    exclude_unusable_icons(ctx, config, &mut candidates, excluded).await;
+
+    if candidates.is_empty() {
+        info!(
+            "Server icon directory '{}' is empty or contains no supported images",
+            unused_dir.display()
+        );
+        return Ok(None);
+    }
+
+    /* The current icon may not be one we applied (e.g. set by hand), so avoid it first */
+    let mut recent = ctx
+        .data
+        .read()
+        .await
+        .get::<State>()
+        .map(|state| state.recent_icons.clone())
+        .unwrap_or_default();
+    if let Some(current) = applied_icon_name(ctx).await {
+        if recent.front() != Some(&current) {
+            recent.push_front(current);
+        }
+    }
+
+    /* A theme flagged remotely wins over the weekday mapping */
+    let (theme, kind) = flag_theme(ctx).await.map_or_else(
+        || {
+            let theme = weekday_theme(config, Utc::now().weekday()).map(String::from);
+            (theme, "today's")
+        },
+        |theme| (Some(theme), "the flagged"),
+    );
+
+    if let Some(theme) = theme.as_deref() {
+        if candidates
+            .iter()
+            .any(|candidate| candidate.meta.theme.as_deref() == Some(theme))
+        {
+            retain_candidates(
+                &mut candidates,
+                &format!("not in {kind} theme"),
+                |candidate| candidate.meta.theme.as_deref() == Some(theme),
+            );
+        } else {
+            debug!("No candidates have {kind} theme '{theme}', using the whole pool");
+        }
+    }
+
+    avoid_recent_icons(&mut candidates, &recent, config.avoid_last_n);
+
+    loop {
+        let Some(selected_icon) = with_rng(ctx, |rng| {
+            select_icon(&candidates, config, &weights, SystemTime::now(), rng)
+        })
+        .await
+        else {
+            info!(
+                "No enabled server icons in '{}' (modified within {} days)",
+                unused_dir.display(),
+                config
+                    .icon_max_age_days
+                    .map_or_else(|| String::from("any number of"), |days| days.to_string())
+            );
+            return Ok(None);
+        };
+
+        match icon_attachment(&selected_icon.path, config.convert_icons).await {
+            Ok(attachment) => {
+                debug!("Selected '{}'", selected_icon.path.display());
+                return Ok(Some((selected_icon, attachment)));
+            }
+            Err(error) => {
+                warn!(
+                    "Skipping server icon '{}': {error}",
+                    selected_icon.path.display()
+                );
+                candidates.retain(|candidate| candidate.path != selected_icon.path);
+                if candidates.is_empty() {
+                    warn!("No usable server icons left in '{}'", unused_dir.display());
+                    return Ok(None);
+                }
+            }
+        }
+    }
+}
+
+/// Drops candidates already tried this rotation, ones Discord rejected before and animated ones
+/// the server can't show.
+async fn exclude_unusable_icons(
+    ctx: &Context,
+    config: &Config,
+    candidates: &mut Vec<IconCandidate>,
+    excluded: &[PathBuf],
+) {
+    if let Some(expired_dir) = &config.expired_icons_dir {
+        archive_expired_icons(config, candidates, expired_dir);
+    }
+
+    let rejected = ctx
+        .data
+        .read()
+        .await
+        .get::<State>()
+        .map(|state| state.rejected_icons.clone())
+        .unwrap_or_default();
+    exclude_rejected_icons(candidates, excluded, &rejected);
+
+    if config.skip_animated_without_feature {
+        match guild_features(ctx, config).await {
+            Ok(features) => {
+                let skipped = exclude_animated_icons(candidates, &features);
+                if skipped > 0 {
+                    warn!(
+                        "The server lost the animated icon feature, skipping {skipped} animated icons"
+                    );
+                }
+            }
+            Err(error) => warn!("Error fetching the server features: {error}"),
+        }
+    }
+}
+
+/// Moves expired icons out of the unused directory into `expired_dir`, dropping them from the
+/// candidates; the rest of the selection already skips expired icons that stay.
+fn archive_expired_icons(config: &Config, candidates: &mut Vec<IconCandidate>, expired_dir: &Path) {
+    if !config.moves_icons() {
+        return;
+    }
+
+    let today = Utc::now().date_naive();
+    candidates.retain(|candidate| {
+        if !candidate.is_expired(today) || !candidate.path.starts_with(&config.server_icons_unused)
+        {
+            return true;
+        }
+
+        match move_icon_file(&candidate.path, expired_dir) {
+            Ok(destination) => {
+                info!(
+                    "Moved expired icon '{}' to '{}'",
+                    candidate.path.display(),
+                    destination.display()
+                );
+                false
+            }
+            Err(error) => {
+                error!(
+                    "Error moving expired icon '{}': {error}",
+                    candidate.path.display()
+                );
+                true
+            }
+        }
+    });
+}
+
+/// The icon theme `weekday_themes` maps the day to, if any.
+fn weekday_theme(config: &Config, weekday: Weekday) -> Option<&str> {
+    config
+        .weekday_themes
+        .iter()
+        .find(|(day, _)| day.parse::<Weekday>().is_ok_and(|day| day == weekday))
+        .map(|(_, theme)| theme.as_str())
+}
+
+/// Keeps the candidates matching `keep`, logging every other one with the reason at debug level.
+fn retain_candidates(
+    candidates: &mut Vec<IconCandidate>,
+    reason: &str,
+    keep: impl Fn(&IconCandidate) -> bool,
+) {
+    candidates.retain(|candidate| {
+        let kept = keep(candidate);
+        if !kept {
+            debug!("Excluding '{}': {reason}", candidate.path.display());
+        }
+
+        kept
+    });
+}
+
+/// Drops animated candidates unless the guild has the `ANIMATED_ICON` feature, returning how
+/// many were dropped.
+fn exclude_animated_icons(candidates: &mut Vec<IconCandidate>, features: &[String]) -> usize {
+    if features.iter().any(|feature| feature == "ANIMATED_ICON") {
+        return 0;
+    }
+
+    let before = candidates.len();
+    retain_candidates(candidates, "animated without the feature", |candidate| {
+        !is_animated_icon(&candidate.path)
+    });
+
+    before - candidates.len()
+}
+
+/// Drops candidates already tried this rotation and the filenames Discord rejected before.
+fn exclude_rejected_icons(
+    candidates: &mut Vec<IconCandidate>,
+    excluded: &[PathBuf],
+    rejected: &BTreeSet<String>,
+) {
+    retain_candidates(candidates, "already tried this rotation", |candidate| {
+        !excluded.contains(&candidate.path)
+    });
+
+    retain_candidates(candidates, "rejected by Discord before", |candidate| {
+        icon_filename(&candidate.path).map_or(true, |name| !rejected.contains(&name))
+    });
+}
+
+/// Drops the `avoid_last_n` most recently applied icons from the candidates, newest first,
+/// stopping early so at least one candidate is always left.
+fn avoid_recent_icons(
+    candidates: &mut Vec<IconCandidate>,
+    recent: &VecDeque<String>,
+    avoid_last_n: usize,
+) {
+    for name in recent.iter().take(avoid_last_n) {
+        let is_other = |candidate: &IconCandidate| {
+            icon_filename(&candidate.path).map_or(true, |other| other != *name)
+        };
+        if !candidates.iter().any(is_other) {
+            debug!("Keeping '{name}' despite being recent, it is the only candidate left");
+            break;
+        }
+
+        retain_candidates(candidates, "applied recently", is_other);
+    }
+}
+
+/// Why an icon is being applied.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum IconSource {
+    /// Picked by the regular rotation and consumed from the unused directory.
+    Rotation,
+    /// Matched to an active calendar event, left in place so it can be shown again.
+    Event,
+    /// Pinned by staff, left in place.
+    Pin,
+    /// Celebrating a member count milestone, left in place.
+    Milestone,
+    /// Picked from the fallback directory, left in place.
+    Fallback,
+    /// Restored from `/icon backup`, left in place.
+    Backup,
+}
+
+impl IconSource {
+    const fn consumes(self) -> bool {
+        matches!(self, Self::Rotation)
+    }
+
+    /// The source of an icon picked from the pool, which is only consumed outside the fallback.
+    fn picked(config: &Config, path: &Path) -> Self {
+        if is_fallback_icon(config, path) {
+            Self::Fallback
+        } else {
+            Self::Rotation
+        }
+    }
+}
+
+/// Uploads the icon to the guild, then moves it to the used directory (when consumed), records
+/// and announces it; callers hold the `IconLock`.
+async fn apply_server_icon(
+    ctx: &Context,
+    config: &Config,
+    icon: &IconCandidate,
+    attachment: &CreateAttachment,
+    source: IconSource,
+) -> Result<String> {
+    let icon_name = icon_filename(&icon.path)?;
+    let builder = EditGuild::new().icon(Some(attachment));
+
+    ctx.data
+        .write()
+        .await
+        .entry::<AppliedIcon>()
+        .or_default()
+        .uploading = true;
+
+    let started = Instant::now();
+    let result = config.guild.edit(&ctx.http, builder).await;
+    debug!(
+        "Uploading server icon '{icon_name}' took {:?}",
+        started.elapsed()
+    );
+
+    let guild = match result {
+        Ok(guild) => guild,
+        Err(error) => {
+            ctx.data
+                .write()
+                .await
+                .entry::<AppliedIcon>()
+                .or_default()
+                .uploading = false;
+
+            /* A rejected image says nothing about Discord or our permissions */
+            let error = Report::from(error);
+            if !is_icon_rejection(&error) {
+                if let Some(error) = error.downcast_ref::<SerenityError>() {
+                    record_icon_failure(ctx, error).await;
+                }
+            }
+
+            return Err(error);
+        }
+    };
+
+    {
+        let mut data = ctx.data.write().await;
+        data.entry::<IconBreaker>().or_default().record_success();
+        data.insert::<AppliedIcon>(AppliedIcon {
+            filename:  Some(icon_name.clone()),
+            hash:      guild.icon,
+            uploading: false,
+        });
+    }
+
+    if let Some(archive_dir) = &config.icon_archive_dir {
+        if let Err(error) = archive_icon(config, archive_dir, &icon.path, &icon_name) {
+            error!("Error archiving server icon '{icon_name}': {error}");
+        }
+    }
+
+    if source.consumes() && config.moves_icons() {
+        move_icon_file(&icon.path, &config.server_icons_used)?;
+    }
+
+    let credit = icon
+        .meta
+        .credit
+        .as_ref()
+        .map(|credit| format!(" (credit: {credit})"))
+        .unwrap_or_default();
+
+    info!(
+        "Updated server icon to '{}' from '{}'{credit}",
+        icon.meta.title(&icon_name),
+        icon.path.display()
+    );
+
+    record_icon_history(ctx, icon_name.clone()).await;
+
+    let shown = icon_name.clone();
+    let avoid_last_n = config.avoid_last_n;
+    let recency_weight_days = config.recency_weight_days;
+    if let Err(error) = update_state(ctx, |state| {
+        let now = Timestamp::now();
+        prune_last_shown(
+            &mut state.last_shown,
+            now.unix_timestamp(),
+            recency_weight_days,
+        );
+        state.last_shown.insert(shown.clone(), now);
+        state.recent_icons.retain(|name| *name != shown);
+        state.recent_icons.push_front(shown);
+        state.recent_icons.truncate(avoid_last_n.max(1));
+    })
+    .await
+    {
+        error!("Error saving state: {error}");
+    }
+    announce_icon_change(ctx, config, &icon_name, icon, attachment, source).await;
+    broadcast_icon_change(ctx, config, &icon_name, icon, attachment).await;
+
+    Ok(icon_name)
+}
+
+/// Shares the applied icon with every `broadcast_targets` entry at once, a failing target only
+/// being logged so the others still go through.
+async fn broadcast_icon_change(
+    ctx: &Context,
+    config: &Config,
+    icon_name: &str,
+    icon: &IconCandidate,
+    attachment: &CreateAttachment,
+) {
+    if config.broadcast_targets.is_empty() {
+        return;
+    }
+
+    let source = ctx.cache.guild(config.guild).map_or_else(
+        || String::from("A partner server"),
+        |guild| guild.name.clone(),
+    );
+    let title = icon.meta.title(icon_name);
+
+    let broadcasts = config.broadcast_targets.iter().map(|target| async {
+        if let Some(guild_id) = target.guild.filter(|guild_id| *guild_id != config.guild) {
+            let builder = EditGuild::new().icon(Some(attachment));
+            match guild_id.edit(&ctx.http, builder).await {
+                Ok(_) => info!("Applied server icon '{icon_name}' to partner guild {guild_id}"),
+                Err(error) => {
+                    error!("Error applying the icon to partner guild {guild_id}: {error}");
+                }
+            }
+        }
+
+        if let Some(channel_id) = target.channel {
+            let embed = CreateEmbed::new()
+                .title("Server icon updated")
+                .description(format!("{source} now shows **{title}**"))
+                .thumbnail(format!("attachment://{}", attachment.filename))
+                .timestamp(Timestamp::now());
+            let message = CreateMessage::new()
+                .embed(embed)
+                .add_file(attachment.clone());
+            if let Err(error) = channel_id.send_message(&ctx.http, message).await {
+                error!("Error broadcasting the icon change to channel {channel_id}: {error}");
+            }
+        }
+    });
+
+    futures::future::join_all(broadcasts).await;
+}
+
+/// Posts the newly applied icon to the log channel, if one is configured.
+async fn announce_icon_change(
+    ctx: &Context,
+    config: &Config,
+    icon_name: &str,
+    applied: &IconCandidate,
+    attachment: &CreateAttachment,
+    source: IconSource,
+) {
+    let Some(channel_id) = config.log_channel else {
+        return;
+    };
+
+    let meta = &applied.meta;
+    let title = meta.title(icon_name);
+    let mut attachment = match config.log_thumbnail_size {
+        Some(size) => {
+            let path = applied.path.clone();
+            let thumbnail = tokio::task::spawn_blocking(move || thumbnail_icon(&path, size.max(1)))
+                .await
+                .map_err(Report::from)
+                .and_then(|thumbnail| thumbnail);
+            match thumbnail {
+                Ok((bytes, filename)) => CreateAttachment::bytes(bytes, filename),
+                Err(error) => {
+                    warn!("Error creating a thumbnail of '{icon_name}', attaching the full icon: {error}");
+                    attachment.clone()
+                }
+            }
+        }
+        None => attachment.clone(),
+    };
+    if let Some(alt_text) = &config.announcement_alt_text {
+        attachment = attachment.description(alt_text.replace("{icon}", title));
+    }
+
+    let description = if title == icon_name {
+        format!("Now showing `{icon_name}`")
+    } else {
+        format!("Now showing **{title}**")
+    };
+
+    let recent = recent_announcement(ctx, config, channel_id).await;
+    let heading = recent.map_or_else(
+        || String::from("Server icon updated"),
+        |last| format!("Server icon changed {} times", last.changes + 1),
+    );
+
+    let mut embed = CreateEmbed::new()
+        .title(heading)
+        .description(description)
+        .timestamp(Timestamp::now());
+
+    /* A spoiler only hides the attachment, so keep the image out of the embed */
+    if source == IconSource::Event && config.spoiler_event_icons {
+        attachment.filename = format!("SPOILER_{}", attachment.filename);
+    } else {
+        embed = embed.thumbnail(format!("attachment://{}", attachment.filename));
+    }
+
+    if let Some(credit) = &meta.credit {
+        embed = embed.field("Credit", credit, true);
+    }
+
+    if let Some(theme) = &meta.theme {
+        embed = embed.field("Theme", theme, true);
+    }
+
+    if let Some(last) = recent {
+        let edit = EditMessage::new()
+            .embed(embed.clone())
+            .new_attachment(attachment.clone());
+        match last
+            .channel_id
+            .edit_message(&ctx.http, last.message_id, edit)
+            .await
+        {
+            Ok(_) => {
+                let mut data = ctx.data.write().await;
+                if let Some(last) = data.get_mut::<LastAnnouncement>() {
+                    last.changes += 1;
+                }
+                drop(data);
+                return;
+            }
+            Err(error) => warn!("Error editing the last icon announcement, posting anew: {error}"),
+        }
+    }
+
+    post_announcement(ctx, channel_id, title, embed, attachment).await;
+}
+
+/// The announcement the latest icon changes were folded into, see
+/// `announcement_min_interval_seconds`.
+#[derive(Clone, Copy)]
+struct LastAnnouncement {
+    /// Where it was announced, the forum itself when `channel_id` is the post's thread.
+    log_channel: ChannelId,
+    channel_id:  ChannelId,
+    message_id:  MessageId,
+    posted_at:   Instant,
+    changes:     u32,
+}
+
+impl TypeMapKey for LastAnnouncement {
+    type Value = Self;
+}
+
+/// The last announcement, if it went to the log channel recently enough to be edited.
+async fn recent_announcement(
+    ctx: &Context,
+    config: &Config,
+    log_channel: ChannelId,
+) -> Option<LastAnnouncement> {
+    let window = Duration::from_secs(config.announcement_min_interval_seconds);
+    let last = ctx.data.read().await.get::<LastAnnouncement>().copied()?;
+
+    (last.log_channel == log_channel && last.posted_at.elapsed() < window).then_some(last)
+}
+
+/// Posts a new announcement and remembers it for the following changes.
+async fn post_announcement(
+    ctx: &Context,
+    channel_id: ChannelId,
+    title: &str,
+    embed: CreateEmbed,
+    attachment: CreateAttachment,
+) {
+    let message = CreateMessage::new().embed(embed).add_file(attachment);
+
+    /* Forum channels only accept threads, so post one per icon change */
+    let is_forum = channel_id
+        .to_channel(ctx)
+        .await
+        .ok()
+        .and_then(Channel::guild)
+        .is_some_and(|channel| channel.kind == ChannelType::Forum);
+
+    /* A forum post's starter message shares the thread's ID */
+    let result = if is_forum {
+        let post = CreateForumPost::new(title, message);
+        channel_id
+            .create_forum_post(ctx, post)
+            .await
+            .map(|thread| (thread.id, MessageId::new(thread.id.get())))
+    } else {
+        channel_id
+            .send_message(&ctx.http, message)
+            .await
+            .map(|message| (channel_id, message.id))
+    };
+
+    match result {
+        Ok((posted_in, message_id)) => {
+            let last = LastAnnouncement {
+                log_channel: channel_id,
+                channel_id: posted_in,
+                message_id,
+                posted_at: Instant::now(),
+                changes: 1,
+            };
+            ctx.data.write().await.insert::<LastAnnouncement>(last);
+        }
+        Err(error) => error!("Error announcing server icon change: {error}"),
+    }
+}
+
+/// Prints the prompt and reads a trimmed line from stdin.
+fn prompt(message: &str) -> Result<String> {
+    print!("{message}");
+    io::stdout().flush()?;
+
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    Ok(line.trim().to_string())
+}
+
+/// Smallest value the setup takes as a guild ID, anything lower can't be a real snowflake.
+const MIN_SNOWFLAKE: u64 = 1 << 22;
+
+/// A guild picked in the setup, either from the listed ones or by ID.
+#[derive(Debug, PartialEq, Eq)]
+enum GuildChoice {
+    Listed(usize),
+    Id(GuildId),
+}
+
+/// Reads a 1-based number into the `listed` guilds, or a snowflake-sized guild ID; other numbers
+/// are invalid rather than taken as an ID.
+fn parse_guild_choice(answer: &str, listed: usize) -> Option<GuildChoice> {
+    let value = answer.parse::<u64>().ok()?;
+    if value >= MIN_SNOWFLAKE {
+        return Some(GuildChoice::Id(GuildId::new(value)));
+    }
+
+    usize::try_from(value)
+        .ok()
+        .and_then(|number| number.checked_sub(1))
+        .filter(|index| *index < listed)
+        .map(GuildChoice::Listed)
+}
+
+/// Asks for the token on first run, validates it and lets the operator pick the guild from the
+/// ones the bot is in, falling back to entering the ID by hand, then saves the config.
+async fn run_setup_wizard(config: &mut Config) -> Result<()> {
+    println!("No Discord token is configured, starting the setup");
+    let token = prompt("Discord bot token: ")?;
+    if token.is_empty() {
+        return Ok(());
+    }
+
+    let user = validate_token(&token).await?;
+    println!("Authenticated as {}", user.name);
+    config.token = token;
+
+    let guilds = match Http::new(&config.token).get_guilds(None, Some(200)).await {
+        Ok(guilds) => guilds,
+        Err(error) => {
+            println!("Could not list the bot's guilds: {error}");
+            Vec::new()
+        }
+    };
+
+    for (index, guild) in guilds.iter().enumerate() {
+        println!("{}. {} ({})", index + 1, guild.name, guild.id);
+    }
+
+    loop {
+        let answer = if guilds.is_empty() {
+            prompt("Guild ID: ")?
+        } else {
+            prompt("Pick a guild by number, or enter a guild ID: ")?
+        };
+
+        let guild_id = match parse_guild_choice(&answer, guilds.len()) {
+            Some(GuildChoice::Listed(index)) => guilds[index].id,
+            Some(GuildChoice::Id(guild_id)) => guild_id,
+            None if guilds.is_empty() => {
+                println!("Please enter a guild ID");
+                continue;
+            }
+            None => {
+                println!("Please enter a number from the list or a guild ID");
+                continue;
+            }
+        };
+
+        config.guild = guild_id;
+        break;
+    }
+
+    config.save()?;
+    println!("Saved the config to '{}'", Config::path()?.display());
+    Ok(())
+}
+
+/// Runs the icon selection against a directory offline, simulating the moves to the used pool in
+/// memory so the folder is left untouched.
+fn selftest_icons(config: &Config, directory: &Path, iterations: usize) -> Result<()> {
+    let mut unused: Vec<IconCandidate> = load_icon_paths(directory, config.follow_symlinks)?
+        .into_iter()
+        .map(IconCandidate::load)
+        .collect();
+    let mut used = Vec::new();
+    let mut rng = new_icon_rng(config);
+    let mut weights = IconWeights {
+        last_shown: State::load().unwrap_or_default().last_shown,
+        ..IconWeights::default()
+    };
+
+    println!(
+        "Found {} icons in '{}', simulating {iterations} rotations",
+        unused.len(),
+        directory.display()
+    );
+
+    for iteration in 1..=iterations {
+        if unused.is_empty() {
+            println!(
+                "{iteration}: pool empty, recycling {} used icons",
+                used.len()
+            );
+            unused.append(&mut used);
+        }
+
+        let Some(selected) = select_icon(&unused, config, &weights, SystemTime::now(), &mut rng)
+        else {
+            println!("{iteration}: no eligible icon");
+            break;
+        };
+
+        let name = icon_filename(&selected.path)?;
+        let credit = selected.meta.credit.as_deref().unwrap_or("none");
+        println!(
+            "{iteration}: {name} (weight {}, credit {credit})",
+            selected.weight()
+        );
+
+        unused.retain(|candidate| candidate.path != selected.path);
+        used.push(selected);
+        weights.last_shown.insert(name, Timestamp::now());
+    }
+
+    Ok(())
+}
+
+/// Whether one directory is the other or inside it, compared after canonicalizing when possible.
+fn dirs_overlap(first: &Path, second: &Path) -> bool {
+    let first = fs::canonicalize(first).unwrap_or_else(|_| first.to_path_buf());
+    let second = fs::canonicalize(second).unwrap_or_else(|_| second.to_path_buf());
+
+    first.starts_with(&second) || second.starts_with(&first)
+}
+
+/// Rejects unused and used icon directories that overlap, where moves and recycling would loop.
+fn validate_icon_dirs(config: &Config) -> Result<()> {
+    let unused = &config.server_icons_unused;
+    let used = &config.server_icons_used;
+    if unused.as_os_str().is_empty() || used.as_os_str().is_empty() {
+        return Ok(());
+    }
+
+    if dirs_overlap(unused, used) {
+        bail!(
+            "server_icons_unused '{}' and server_icons_used '{}' must be separate directories",
+            unused.display(),
+            used.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Loads the config from the file, or as TOML from stdin with `--config-stdin`; `--token-stdin`
+/// reads only the token from stdin, so secret managers never have to write it to disk.
+fn load_config(config_stdin: bool, token_stdin: bool) -> Result<Config> {
+    let read_stdin = || -> Result<String> {
+        let mut text = String::new();
+        io::Read::read_to_string(&mut io::stdin(), &mut text)?;
+        Ok(text)
+    };
+
+    if config_stdin {
+        return toml::from_str(&read_stdin()?)
+            .map_err(|error| eyre!("Failed to parse the config from stdin: {error}"));
+    }
+
+    let mut config = Config::load().unwrap_or_default();
+    if token_stdin {
+        config.token = read_stdin()?.trim().to_string();
+    }
+
+    Ok(config)
+}
+
+/// Replaces the running config with the config file, returning the changed fields; the token
+/// can't change without reconnecting, so such a reload is rejected. Settings read at startup,
+/// like the intents, the log file and which background loops run, still need a restart.
+async fn reload_config(data: &RwLock<TypeMap>, token_stdin: bool) -> Result<Vec<String>> {
+    let mut reloaded = Config::load()?;
+    let Some(current) = data.read().await.get::<Config>().cloned() else {
+        bail!("no config is loaded yet");
+    };
+
+    /* A token passed on stdin never is in the file */
+    if token_stdin {
+        reloaded.token.clone_from(&current.token);
+    }
+
+    if reloaded.token != current.token {
+        bail!("the token changed, restart the bot to use it");
+    }
+
+    validate_icon_dirs(&reloaded)?;
+    validate_config_values(&reloaded)?;
+
+    let changes = config_diff(&current, &reloaded)?;
+    let delay_changed = current.icon_delay_range().ok() != reloaded.icon_delay_range().ok();
+
+    let mut data = data.write().await;
+    data.insert::<Config>(reloaded);
+
+    /* Wakes the rotation loop, including one idling because the delay was disabled */
+    if delay_changed {
+        data.entry::<IconReschedule>().or_default().notify_one();
+    }
+    drop(data);
+
+    Ok(changes)
+}
+
+/// Reloads the config file on every SIGHUP, logging the outcome instead of stopping the bot.
+#[cfg(unix)]
+async fn run_reload_signal_loop(data: Arc<RwLock<TypeMap>>, token_stdin: bool) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(error) => {
+            error!("Error listening for SIGHUP, config reloads are unavailable: {error}");
+            return;
+        }
+    };
+
+    while hangups.recv().await.is_some() {
+        match reload_config(&data, token_stdin).await {
+            Ok(changes) if changes.is_empty() => info!("Reloaded the config, nothing changed"),
+            Ok(changes) => info!("Reloaded the config: {}", changes.join(", ")),
+            Err(error) => error!("Error reloading the config, keeping the running one: {error}"),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let config_stdin = args.iter().any(|arg| arg == "--config-stdin");
+    let token_stdin = args.iter().any(|arg| arg == "--token-stdin");
+
+    let mut config = load_config(config_stdin, token_stdin)?;
+
+    if let Some(index) = args.iter().position(|arg| arg == "--selftest-icons") {
+        let Some(directory) = args.get(index + 1) else {
+            bail!("--selftest-icons needs a directory");
+        };
+
+        let iterations = args
+            .iter()
+            .position(|arg| arg == "--iterations")
+            .and_then(|index| args.get(index + 1))
+            .map_or(Ok(10), |value| value.parse())?;
+
+        return selftest_icons(&config, Path::new(directory), iterations);
+    }
+
+    let log_filter = init_logging(&config)?;
+
+    validate_icon_dirs(&config)?;
+    validate_config_values(&config)?;
+
+    if config.token.is_empty() && !config_stdin && !token_stdin && io::stdin().is_terminal() {
+        run_setup_wizard(&mut config).await?;
+    }
+
+    if config.token.is_empty() {
+        error!("You must provide a Discord token in the config file");
+        if !config_stdin {
+            config.save()?;
+        }
+
+        return Ok(());
+    }
+
+    let user = validate_token(&config.token).await?;
+    info!("Authenticated as {}", user.name);
+
+    if let Err(error) = sync_icon_repo(&config).await {
+        warn!("Error syncing the icon repository, keeping the last clone: {error}");
+    }
+
+    let intents = gateway_intents(&config)?;
+    warn_missing_intents(&config, intents);
+
+    let mut client = Client::builder(&config.token, intents)
+        .event_handler(Events)
+        .await?;
+
+    {
+        let mut data = client.data.write().await;
+        data.insert::<IconRng>(new_icon_rng(&config));
+        data.insert::<State>(State::load().unwrap_or_default());
+        data.insert::<Config>(config);
+        data.insert::<LogFilter>(log_filter);
+    }
+
+    /* Config given on stdin has no file to reload from */
+    #[cfg(unix)]
+    if !config_stdin {
+        tokio::spawn(run_reload_signal_loop(client.data.clone(), token_stdin));
+    }
+
+    info!("Starting...");
+    if let Err(error) = client.start().await {
+        if matches!(
+            error,
+            SerenityError::Gateway(GatewayError::DisallowedGatewayIntents)
+        ) {
+            bail!("Discord rejected the requested intents; enable the privileged intents listed in additional_intents in the Developer Portal");
+        }
+
+        return Err(error.into());
+    }
+
+    bail!("Unreachable")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{self, File},
+        path::{Path, PathBuf},
+        sync::atomic::{AtomicUsize, Ordering},
+        time::{Duration, SystemTime},
+    };
+
+    use chrono::TimeZone;
+
+    use super::*;
+
+    /// A scratch directory removed again on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let name = format!(
+                "thedevsbot-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            );
+            let path = std::env::temp_dir().join(name);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn path(&self) -> &Path {
+            &self.0
+        }
+
+        fn subdir(&self, name: &str) -> PathBuf {
+            let path = self.0.join(name);
+            fs::create_dir_all(&path).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Creates an empty file, optionally backdating its modification time by `age`.
+    fn touch(dir: &Path, name: &str, age: Option<Duration>) -> PathBuf {
+        let path = dir.join(name);
+        let file = File::create(&path).unwrap();
+        if let Some(age) = age {
+            file.set_modified(SystemTime::now() - age).unwrap();
+        }
+        path
+    }
+
+    fn names(paths: &[PathBuf]) -> Vec<String> {
+        let mut names = paths
+            .iter()
+            .map(|path| icon_filename(path).unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    fn icon_candidates(temp: &TempDir, names: &[&str]) -> Vec<IconCandidate> {
+        names
+            .iter()
+            .map(|name| IconCandidate::load(touch(temp.path(), name, None)))
+            .collect()
+    }
+
+    fn candidate_names(candidates: &[IconCandidate]) -> Vec<String> {
+        let paths = candidates
+            .iter()
+            .map(|candidate| candidate.path.clone())
+            .collect::<Vec<_>>();
+        names(&paths)
+    }
+
+    fn timestamp(seconds: i64) -> Timestamp {
+        Timestamp::from_unix_timestamp(seconds).unwrap()
+    }
+
+    #[test]
+    fn constant_time_eq_compares_contents_and_lengths() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"secret"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[test]
+    fn recycle_batch_takes_longest_ago_shown_not_oldest_mtime() {
+        let temp = TempDir::new();
+        let unused = temp.subdir("unused");
+        let used = temp.subdir("used");
+        /* Modification times run opposite to the order the icons were applied in */
+        touch(&used, "a.png", Some(Duration::from_secs(10)));
+        touch(&used, "b.png", Some(Duration::from_mins(30)));
+        touch(&used, "c.png", Some(Duration::from_mins(20)));
+        touch(&used, "d.png", None);
+        let last_shown = BTreeMap::from([
+            (String::from("a.png"), timestamp(1_000)),
+            (String::from("b.png"), timestamp(3_000)),
+            (String::from("c.png"), timestamp(2_000)),
+        ]);
+
+        let moved = recycle_used_icons(&unused, &used, Some(2), false, &last_shown).unwrap();
+        assert_eq!(names(&moved), ["a.png", "d.png"]);
+        assert!(unused.join("a.png").exists());
+        assert!(unused.join("d.png").exists());
+        assert!(used.join("b.png").exists());
+        assert!(used.join("c.png").exists());
+
+        let moved = recycle_used_icons(&unused, &used, Some(1), false, &last_shown).unwrap();
+        assert_eq!(names(&moved), ["c.png"]);
+    }
+
+    #[test]
+    fn required_intents_are_only_privileged() {
+        let config = Config {
+            text_command_prefix: Some(String::from("!")),
+            bot_nickname: Some(String::from("bot")),
+            ..Config::default()
+        };
+
+        let required = required_intents(&config);
+        assert!(!required.is_empty());
+        for (intent, feature) in required {
+            assert!(
+                !GatewayIntents::non_privileged().intersects(intent),
+                "{feature} lists a non-privileged intent"
+            );
+        }
+    }
+
+    #[test]
+    fn listing_candidates_moves_nothing() {
+        let temp = TempDir::new();
+        let config = Config {
+            server_icons_unused: temp.subdir("unused"),
+            server_icons_used: temp.subdir("used"),
+            ..Config::default()
+        };
+        touch(&config.server_icons_used, "a.png", None);
+
+        let candidates = list_icon_candidates(&config).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert!(config.server_icons_used.join("a.png").exists());
+        assert!(load_icon_paths(&config.server_icons_unused, false)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn guild_choice_takes_only_listed_numbers_or_snowflakes() {
+        assert_eq!(parse_guild_choice("1", 3), Some(GuildChoice::Listed(0)));
+        assert_eq!(parse_guild_choice("3", 3), Some(GuildChoice::Listed(2)));
+        assert_eq!(parse_guild_choice("0", 3), None);
+        assert_eq!(parse_guild_choice("4", 3), None);
+        assert_eq!(parse_guild_choice("42", 0), None);
+        assert_eq!(parse_guild_choice("guild", 3), None);
+        assert_eq!(
+            parse_guild_choice("81384788765712384", 3),
+            Some(GuildChoice::Id(GuildId::new(81_384_788_765_712_384)))
+        );
+    }
+
+    #[test]
+    fn last_shown_pruned_past_the_recency_window() {
+        let day = 86_400;
+        let now = 100 * day;
+        let mut last_shown = BTreeMap::from([
+            (String::from("old.png"), timestamp(now - 8 * day)),
+            (String::from("edge.png"), timestamp(now - 7 * day)),
+            (String::from("new.png"), timestamp(now - day)),
+        ]);
+
+        prune_last_shown(&mut last_shown, now, None);
+        assert_eq!(last_shown.len(), 3);
+
+        prune_last_shown(&mut last_shown, now, Some(7));
+        assert_eq!(
+            last_shown.keys().collect::<Vec<_>>(),
+            ["edge.png", "new.png"]
+        );
+    }
+
+    #[test]
+    fn rejected_icon_skipped_for_the_second_candidate() {
+        let temp = TempDir::new();
+        let first = IconCandidate::load(touch(temp.path(), "first.png", None));
+        let second = IconCandidate::load(touch(temp.path(), "second.png", None));
+        let config = Config::default();
+        let weights = IconWeights::default();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        /* Discord refused the first upload, so the retry has to land on the other icon */
+        let mut candidates = vec![first.clone(), second.clone()];
+        let rejected = BTreeSet::from([String::from("first.png")]);
+        exclude_rejected_icons(
+            &mut candidates,
+            std::slice::from_ref(&first.path),
+            &rejected,
+        );
+        let selected =
+            select_icon(&candidates, &config, &weights, SystemTime::now(), &mut rng).unwrap();
+        assert_eq!(selected.path, second.path);
+
+        /* Once the rejections are cleared the first icon is eligible again next rotation */
+        let mut candidates = vec![first.clone(), second];
+        exclude_rejected_icons(&mut candidates, &[], &BTreeSet::new());
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[0].path, first.path);
+    }
+
+    #[test]
+    fn preset_toggle_adds_missing_then_removes_all() {
+        let other = RoleId::new(1);
+        let first = RoleId::new(2);
+        let second = RoleId::new(3);
+        let preset = [first, second];
+
+        let added = toggle_preset_roles(&[other, first], &preset);
+        assert_eq!(
+            added,
+            PresetToggle {
+                roles:   vec![other, first, second],
+                removed: false,
+                changed: 1,
+            }
+        );
+
+        let removed = toggle_preset_roles(&added.roles, &preset);
+        assert_eq!(
+            removed,
+            PresetToggle {
+                roles:   vec![other],
+                removed: true,
+                changed: 2,
+            }
+        );
+    }
+
+    fn mode_problems(config: &Config) -> String {
+        validate_mode_requirements(config)
+            .map_or_else(|error| error.to_string(), |()| String::new())
+    }
+
+    #[test]
+    fn mode_requirements_default_config_is_valid() {
+        assert_eq!(mode_problems(&Config::default()), "");
+    }
+
+    #[test]
+    fn mode_requirements_approval_needs_a_channel() {
+        let mut config = Config {
+            icon_requires_approval: true,
+            ..Config::default()
+        };
+        assert!(mode_problems(&config).contains("icon_requires_approval"));
+
+        config.log_channel = Some(ChannelId::new(1));
+        assert_eq!(mode_problems(&config), "");
+
+        config.log_channel = None;
+        config.approval_channel = Some(ChannelId::new(1));
+        assert_eq!(mode_problems(&config), "");
+    }
+
+    #[test]
+    fn mode_requirements_success_reaction() {
+        let mut config = Config {
+            success_reaction_only: true,
+            ..Config::default()
+        };
+        assert!(mode_problems(&config).contains("success_reaction_only needs success_reaction"));
+
+        config.success_reaction = Some(String::from("<:broken"));
+        assert!(mode_problems(&config).contains("not a valid emoji"));
+
+        config.success_reaction = Some(String::from("✅"));
+        assert_eq!(mode_problems(&config), "");
+    }
+
+    #[test]
+    fn mode_requirements_weekday_themes_need_names() {
+        let mut config = Config {
+            weekday_themes: BTreeMap::from([(String::from("mon"), String::new())]),
+            ..Config::default()
+        };
+        assert!(mode_problems(&config).contains("weekday_themes"));
+
+        config
+            .weekday_themes
+            .insert(String::from("mon"), String::from("dark"));
+        assert_eq!(mode_problems(&config), "");
+    }
+
+    #[test]
+    fn mode_requirements_http_api_token_needs_address() {
+        let mut config = Config {
+            http_api_token: Some(String::from("secret")),
+            ..Config::default()
+        };
+        assert!(mode_problems(&config).contains("http_api_token needs http_api_address"));
+
+        config.http_api_address = Some(String::from("127.0.0.1:8080"));
+        assert_eq!(mode_problems(&config), "");
+    }
+
+    #[test]
+    fn mode_requirements_conversion_follows_the_feature() {
+        let config = Config {
+            convert_icons: true,
+            log_thumbnail_size: Some(64),
+            ..Config::default()
+        };
+        let problems = mode_problems(&config);
+        assert_eq!(
+            problems.contains("convert_icons"),
+            !cfg!(feature = "convert")
+        );
+        assert_eq!(
+            problems.contains("log_thumbnail_size"),
+            !cfg!(feature = "convert")
+        );
+    }
+
+    #[test]
+    fn mode_requirements_lists_every_problem() {
+        let config = Config {
+            icon_requires_approval: true,
+            success_reaction_only: true,
+            http_api_token: Some(String::from("secret")),
+            ..Config::default()
+        };
+        let problems = mode_problems(&config);
+        assert!(problems.contains("icon_requires_approval"));
+        assert!(problems.contains("success_reaction_only"));
+        assert!(problems.contains("http_api_token"));
+    }
+
+    #[test]
+    fn max_age_compares_the_modification_time() {
+        let temp = TempDir::new();
+        let path = touch(temp.path(), "old.png", Some(Duration::from_hours(48)));
+        let now = SystemTime::now();
+
+        assert!(is_within_max_age(&path, None, now));
+        assert!(is_within_max_age(&path, Some(3), now));
+        assert!(!is_within_max_age(&path, Some(1), now));
+        assert!(!is_within_max_age(&path, Some(0), now));
+        assert!(is_within_max_age(
+            &temp.path().join("missing.png"),
+            Some(1),
+            now
+        ));
+    }
+
+    #[test]
+    fn select_icon_skips_icons_past_the_max_age() {
+        let temp = TempDir::new();
+        let old = IconCandidate::load(touch(
+            temp.path(),
+            "old.png",
+            Some(Duration::from_hours(240)),
+        ));
+        let new = IconCandidate::load(touch(temp.path(), "new.png", Some(Duration::from_hours(1))));
+        let candidates = [old.clone(), new.clone()];
+        let weights = IconWeights::default();
+        let now = SystemTime::now();
+        let mut config = Config {
+            icon_max_age_days: Some(7),
+            ..Config::default()
+        };
+
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let selected = select_icon(&candidates, &config, &weights, now, &mut rng).unwrap();
+            assert_eq!(selected.path, new.path);
+        }
+
+        config.icon_max_age_days = Some(0);
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(select_icon(&candidates, &config, &weights, now, &mut rng).is_none());
+
+        config.icon_max_age_days = None;
+        let picked = (0..50)
+            .map(|seed| {
+                let mut rng = StdRng::seed_from_u64(seed);
+                select_icon(&candidates, &config, &weights, now, &mut rng)
+                    .unwrap()
+                    .path
+            })
+            .collect::<BTreeSet<_>>();
+        assert!(picked.contains(&old.path));
+    }
+
+    #[test]
+    fn cross_device_move_copies_and_removes() {
+        let temp = TempDir::new();
+        let unused = temp.subdir("unused");
+        let used = temp.subdir("used");
+        let source = unused.join("icon.png");
+        let destination = used.join("icon.png");
+        fs::write(&source, b"new").unwrap();
+        fs::write(&destination, b"stale").unwrap();
+
+        move_file_across(&source, &destination, true).unwrap();
+        assert!(!source.exists());
+        assert_eq!(fs::read(&destination).unwrap(), b"new");
+
+        /* A failed copy leaves the source alone */
+        let missing_dir = temp.path().join("missing").join("icon.png");
+        assert!(move_file_across(&destination, &missing_dir, true).is_err());
+        assert!(destination.exists());
+    }
+
+    #[test]
+    fn copy_and_remove_keeps_the_contents() {
+        let temp = TempDir::new();
+        let source = temp.path().join("a.png");
+        let destination = temp.subdir("used").join("a.png");
+        fs::write(&source, b"icon").unwrap();
+
+        copy_and_remove(&source, &destination).unwrap();
+        assert!(!source.exists());
+        assert_eq!(fs::read(&destination).unwrap(), b"icon");
+    }
+
+    #[test]
+    fn icon_dirs_overlap_when_identical_nested_or_slashed() {
+        let temp = TempDir::new();
+        let icons = temp.subdir("icons");
+        let nested = temp.subdir("icons/used");
+        let sibling = temp.subdir("icons-used");
+        let slashed = PathBuf::from(format!("{}/", icons.display()));
+
+        assert!(dirs_overlap(&icons, &icons));
+        assert!(dirs_overlap(&icons, &slashed));
+        assert!(dirs_overlap(&icons, &nested));
+        assert!(dirs_overlap(&nested, &icons));
+        assert!(!dirs_overlap(&icons, &sibling));
+
+        /* Directories that don't exist yet are compared as written */
+        let missing = temp.path().join("missing");
+        assert!(dirs_overlap(&missing, &missing.join("used")));
+        assert!(!dirs_overlap(&missing, &icons));
+    }
+
+    #[test]
+    fn validate_icon_dirs_rejects_overlaps() {
+        let temp = TempDir::new();
+        let mut config = Config {
+            server_icons_unused: temp.subdir("unused"),
+            server_icons_used: temp.subdir("used"),
+            ..Config::default()
+        };
+        assert!(validate_icon_dirs(&config).is_ok());
+
+        config.server_icons_used = config.server_icons_unused.join("used");
+        assert!(validate_icon_dirs(&config).is_err());
+
+        config.server_icons_used =
+            PathBuf::from(format!("{}/", config.server_icons_unused.display()));
+        assert!(validate_icon_dirs(&config).is_err());
+
+        config.server_icons_used = PathBuf::new();
+        assert!(validate_icon_dirs(&config).is_ok());
+    }
+
+    #[test]
+    fn stream_move_covers_joining_already_streaming() {
+        let pair = VoicePair {
+            voice: ChannelId::new(1),
+            video: ChannelId::new(2),
+        };
+        let elsewhere = ChannelId::new(3);
+
+        /* Joined straight into a stream, from nowhere or another channel */
+        assert_eq!(
+            stream_move(None, pair.voice, Some(true), pair, true),
+            StreamMove::Move
+        );
+        assert_eq!(
+            stream_move(Some(elsewhere), pair.voice, Some(true), pair, true),
+            StreamMove::Move
+        );
+        assert_eq!(
+            stream_move(None, pair.voice, Some(true), pair, false),
+            StreamMove::JoinedStreaming
+        );
+
+        /* Started streaming once inside, regardless of the join setting */
+        assert_eq!(
+            stream_move(Some(pair.voice), pair.voice, Some(true), pair, false),
+            StreamMove::Move
+        );
+
+        /* Not streaming, or streaming somewhere else */
+        assert_eq!(
+            stream_move(None, pair.voice, Some(false), pair, true),
+            StreamMove::Stay
+        );
+        assert_eq!(
+            stream_move(None, pair.voice, None, pair, true),
+            StreamMove::Stay
+        );
+        assert_eq!(
+            stream_move(None, pair.video, Some(true), pair, true),
+            StreamMove::Stay
+        );
+    }
+
+    #[test]
+    fn voice_pairs_include_the_legacy_pair_first() {
+        let second = VoicePair {
+            voice: ChannelId::new(3),
+            video: ChannelId::new(4),
+        };
+        let mut config = Config {
+            voice_pairs: vec![second],
+            ..Config::default()
+        };
+        assert_eq!(voice_pairs(&config), [second]);
+
+        /* The default channel ID is 1, so the legacy pair uses other IDs */
+        config.voice = ChannelId::new(10);
+        config.video = ChannelId::new(20);
+        let first = VoicePair {
+            voice: config.voice,
+            video: config.video,
+        };
+        assert_eq!(voice_pairs(&config), [first, second]);
+    }
+
+    #[test]
+    fn find_voice_pair_tells_two_pairs_apart() {
+        let first = VoicePair {
+            voice: ChannelId::new(1),
+            video: ChannelId::new(2),
+        };
+        let second = VoicePair {
+            voice: ChannelId::new(3),
+            video: ChannelId::new(4),
+        };
+        let pairs = [first, second];
+
+        assert_eq!(find_voice_pair(&pairs, ChannelId::new(1)), Some(first));
+        assert_eq!(find_voice_pair(&pairs, ChannelId::new(2)), Some(first));
+        assert_eq!(find_voice_pair(&pairs, ChannelId::new(3)), Some(second));
+        assert_eq!(find_voice_pair(&pairs, ChannelId::new(4)), Some(second));
+        assert_eq!(find_voice_pair(&pairs, ChannelId::new(5)), None);
+
+        /* Moving from the first voice channel into the second, streaming, leaves the first pair */
+        let old_pair = find_voice_pair(&pairs, first.voice);
+        let new_pair = find_voice_pair(&pairs, second.voice);
+        assert_ne!(old_pair, new_pair);
+        assert_eq!(
+            stream_move(Some(first.voice), second.voice, Some(true), second, false),
+            StreamMove::JoinedStreaming
+        );
+        assert_eq!(
+            stream_move(Some(first.voice), second.voice, Some(true), second, true),
+            StreamMove::Move
+        );
+
+        /* Being moved into the video channel of the same pair keeps it */
+        assert_eq!(
+            find_voice_pair(&pairs, first.video),
+            find_voice_pair(&pairs, first.voice)
+        );
+    }
+
+    #[test]
+    fn load_candidates_falls_back_once_nothing_can_be_recycled() {
+        let temp = TempDir::new();
+        let fallback = temp.subdir("fallback");
+        touch(&fallback, "fallback.png", None);
+        let mut config = Config {
+            server_icons_unused: temp.subdir("unused"),
+            server_icons_used: temp.subdir("used"),
+            server_icons_fallback: Some(fallback.clone()),
+            ..Config::default()
+        };
+
+        let candidates = load_icon_candidates(&config, &BTreeMap::new()).unwrap();
+        assert_eq!(candidate_names(&candidates), ["fallback.png"]);
+        assert!(fallback.join("fallback.png").exists());
+
+        /* Used icons are recycled before the fallback is touched */
+        touch(&config.server_icons_used, "used.png", None);
+        let candidates = load_icon_candidates(&config, &BTreeMap::new()).unwrap();
+        assert_eq!(candidate_names(&candidates), ["used.png"]);
+        assert!(config.server_icons_unused.join("used.png").exists());
+
+        /* Without moves nothing is recycled, so the fallback steps in right away */
+        move_icon_file(
+            &config.server_icons_unused.join("used.png"),
+            &config.server_icons_used,
+        )
+        .unwrap();
+        config.move_applied_icons = false;
+        let candidates = load_icon_candidates(&config, &BTreeMap::new()).unwrap();
+        assert_eq!(candidate_names(&candidates), ["fallback.png"]);
+        assert!(config.server_icons_used.join("used.png").exists());
+    }
+
+    #[test]
+    fn avoid_recent_with_a_pool_larger_than_the_window() {
+        let temp = TempDir::new();
+        let mut candidates = icon_candidates(&temp, &["a.png", "b.png", "c.png", "d.png", "e.png"]);
+        let recent = VecDeque::from([
+            String::from("c.png"),
+            String::from("b.png"),
+            String::from("a.png"),
+        ]);
+
+        avoid_recent_icons(&mut candidates, &recent, 2);
+        assert_eq!(candidate_names(&candidates), ["a.png", "d.png", "e.png"]);
+    }
+
+    #[test]
+    fn avoid_recent_with_a_pool_smaller_than_the_window() {
+        let temp = TempDir::new();
+        let mut candidates = icon_candidates(&temp, &["a.png", "b.png"]);
+        let recent = VecDeque::from([
+            String::from("a.png"),
+            String::from("b.png"),
+            String::from("c.png"),
+        ]);
+
+        /* The newest is dropped, the last one left is kept even though it is recent too */
+        avoid_recent_icons(&mut candidates, &recent, 5);
+        assert_eq!(candidate_names(&candidates), ["b.png"]);
+
+        let mut candidates = icon_candidates(&temp, &["a.png", "b.png"]);
+        avoid_recent_icons(&mut candidates, &recent, 0);
+        assert_eq!(candidate_names(&candidates), ["a.png", "b.png"]);
+    }
+
+    #[test]
+    fn single_icon_is_picked_even_when_recent() {
+        let temp = TempDir::new();
+        let mut candidates = icon_candidates(&temp, &["only.png"]);
+        let recent = VecDeque::from([String::from("only.png")]);
+
+        avoid_recent_icons(&mut candidates, &recent, 3);
+        let mut rng = StdRng::seed_from_u64(0);
+        let selected = select_icon(
+            &candidates,
+            &Config::default(),
+            &IconWeights::default(),
+            SystemTime::now(),
+            &mut rng,
+        )
+        .unwrap();
+        assert_eq!(icon_filename(&selected.path).unwrap(), "only.png");
+    }
+
+    #[test]
+    fn two_icons_alternate() {
+        let temp = TempDir::new();
+        let recent = VecDeque::from([String::from("a.png")]);
+
+        for seed in 0..20 {
+            let mut candidates = icon_candidates(&temp, &["a.png", "b.png"]);
+            avoid_recent_icons(&mut candidates, &recent, 1);
+            let mut rng = StdRng::seed_from_u64(seed);
+            let selected = select_icon(
+                &candidates,
+                &Config::default(),
+                &IconWeights::default(),
+                SystemTime::now(),
+                &mut rng,
+            )
+            .unwrap();
+            assert_eq!(icon_filename(&selected.path).unwrap(), "b.png");
+        }
+    }
+
+    #[test]
+    fn recycled_pool_skips_the_last_applied_icon() {
+        let temp = TempDir::new();
+        let unused = temp.subdir("unused");
+        let used = temp.subdir("used");
+        for name in ["a.png", "b.png", "c.png"] {
+            touch(&used, name, None);
+        }
+
+        /* The recent list survives the recycle, so the icon shown last doesn't come right back */
+        recycle_used_icons(&unused, &used, None, false, &BTreeMap::new()).unwrap();
+        let recent = VecDeque::from([String::from("c.png"), String::from("b.png")]);
+        for seed in 0..20 {
+            let mut candidates = load_icon_paths(&unused, false)
+                .unwrap()
+                .into_iter()
+                .map(IconCandidate::load)
+                .collect::<Vec<_>>();
+            avoid_recent_icons(&mut candidates, &recent, 2);
+            let mut rng = StdRng::seed_from_u64(seed);
+            let selected = select_icon(
+                &candidates,
+                &Config::default(),
+                &IconWeights::default(),
+                SystemTime::now(),
+                &mut rng,
+            )
+            .unwrap();
+            assert_eq!(icon_filename(&selected.path).unwrap(), "a.png");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_icons_are_skipped_or_moved_as_links() {
+        let temp = TempDir::new();
+        let shared = temp.subdir("shared");
+        let unused = temp.subdir("unused");
+        let used = temp.subdir("used");
+        let target = shared.join("real.png");
+        fs::write(&target, b"icon").unwrap();
+        std::os::unix::fs::symlink("../shared/real.png", unused.join("link.png")).unwrap();
+
+        assert!(load_icon_paths(&unused, false).unwrap().is_empty());
+        assert_eq!(
+            names(&load_icon_paths(&unused, true).unwrap()),
+            ["link.png"]
+        );
+
+        /* The relative link would dangle from another directory, so it is recreated absolute */
+        let destination = used.join("link.png");
+        move_symlink(&unused.join("link.png"), &destination).unwrap();
+        assert!(!unused.join("link.png").is_symlink());
+        assert!(destination.is_symlink());
+        assert_eq!(fs::read(&destination).unwrap(), b"icon");
+        assert_eq!(fs::read(&target).unwrap(), b"icon");
+    }
+
+    /// A minimal extended WebP header, with the animation flag set when `animated`.
+    fn webp_header(animated: bool) -> Vec<u8> {
+        let mut header = b"RIFF\0\0\0\0WEBPVP8X\0\0\0\0".to_vec();
+        header.push(if animated { 0x02 } else { 0x00 });
+        header.extend_from_slice(&[0; 9]);
+        header
+    }
+
+    #[test]
+    fn animated_icons_are_detected_by_format_and_header() {
+        let temp = TempDir::new();
+        let dir = temp.path();
+        fs::write(dir.join("animated.webp"), webp_header(true)).unwrap();
+        fs::write(dir.join("still.webp"), webp_header(false)).unwrap();
+        fs::write(dir.join("short.webp"), b"RIFF").unwrap();
+        touch(dir, "any.gif", None);
+        touch(dir, "upper.GIF", None);
+        touch(dir, "still.png", None);
+
+        assert!(is_animated_icon(&dir.join("animated.webp")));
+        assert!(!is_animated_icon(&dir.join("still.webp")));
+        assert!(!is_animated_icon(&dir.join("short.webp")));
+        assert!(!is_animated_icon(&dir.join("missing.webp")));
+        assert!(is_animated_icon(&dir.join("any.gif")));
+        assert!(is_animated_icon(&dir.join("upper.GIF")));
+        assert!(!is_animated_icon(&dir.join("still.png")));
+    }
+
+    #[test]
+    fn animated_icons_need_the_animated_icon_feature() {
+        let temp = TempDir::new();
+        fs::write(temp.path().join("animated.webp"), webp_header(true)).unwrap();
+        let all = icon_candidates(&temp, &["any.gif", "still.png"])
+            .into_iter()
+            .chain([IconCandidate::load(temp.path().join("animated.webp"))])
+            .collect::<Vec<_>>();
+
+        let mut candidates = all.clone();
+        let features = [String::from("BANNER")];
+        assert_eq!(exclude_animated_icons(&mut candidates, &features), 2);
+        assert_eq!(candidate_names(&candidates), ["still.png"]);
+
+        let mut candidates = all;
+        let features = [String::from("BANNER"), String::from("ANIMATED_ICON")];
+        assert_eq!(exclude_animated_icons(&mut candidates, &features), 0);
+        assert_eq!(candidates.len(), 3);
+    }
+
+    #[test]
+    fn weekday_theme_maps_all_seven_days() {
+        let days = [
+            ("mon", Weekday::Mon),
+            ("Tuesday", Weekday::Tue),
+            ("wed", Weekday::Wed),
+            ("THU", Weekday::Thu),
+            ("friday", Weekday::Fri),
+            ("sat", Weekday::Sat),
+            ("sun", Weekday::Sun),
+        ];
+        let mut config = Config {
+            weekday_themes: days
+                .iter()
+                .map(|(key, day)| {
+                    (
+                        key.to_string(),
+                        format!("theme-{}", day.num_days_from_monday()),
+                    )
+                })
+                .collect(),
+            ..Config::default()
+        };
+
+        for (_, day) in days {
+            let expected = format!("theme-{}", day.num_days_from_monday());
+            assert_eq!(weekday_theme(&config, day), Some(expected.as_str()));
+        }
+
+        /* Unknown keys never match, and unmapped days have no theme */
+        config.weekday_themes = BTreeMap::from([
+            (String::from("someday"), String::from("odd")),
+            (String::from("mon"), String::from("dark")),
+        ]);
+        assert_eq!(weekday_theme(&config, Weekday::Mon), Some("dark"));
+        assert_eq!(weekday_theme(&config, Weekday::Tue), None);
+    }
+
+    #[test]
+    fn archive_name_fills_every_placeholder() {
+        let now = Utc.with_ymd_and_hms(2026, 3, 4, 5, 6, 7).unwrap();
+        let render = |template| render_archive_name(template, "cat.v2.png", now).unwrap();
+
+        assert_eq!(render("{date}"), "2026-03-04");
+        assert_eq!(render("{time}"), "050607");
+        assert_eq!(render("{epoch}"), now.timestamp().to_string());
+        assert_eq!(render("{name}"), "cat.v2.png");
+        assert_eq!(render("{stem}"), "cat.v2");
+        assert_eq!(render("{ext}"), "png");
+        assert_eq!(render("{date}-{stem}.{ext}"), "2026-03-04-cat.v2.png");
+        assert_eq!(render("plain.png"), "plain.png");
+        assert_eq!(
+            render_archive_name("{stem}.{ext}", "noext", now).unwrap(),
+            "noext."
+        );
+    }
+
+    #[test]
+    fn archive_name_rejects_bad_templates() {
+        let now = Utc.with_ymd_and_hms(2026, 3, 4, 5, 6, 7).unwrap();
+        let error = |template| {
+            render_archive_name(template, "cat.png", now)
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert!(error("{nope}-{name}").contains("unknown placeholder '{nope}'"));
+        assert!(error("{name").contains("unclosed placeholder"));
+        assert!(error("{date}/{name}").contains("plain filename"));
+        assert!(error("..\\{name}").contains("plain filename"));
+        assert!(error("").contains("plain filename"));
+        assert!(error("..").contains("plain filename"));
+    }
+
+    #[test]
+    fn delay_range_parses_durations_over_hours() {
+        let mut config = Config {
+            server_icons_delay_min: Some(String::from("30m")),
+            server_icons_delay_max: Some(String::from("1h30m")),
+            server_icons_delay_min_hours: 5,
+            server_icons_delay_max_hours: 6,
+            ..Config::default()
+        };
+        assert_eq!(
+            config.icon_delay_range().unwrap(),
+            (Duration::from_mins(30), Duration::from_mins(90))
+        );
+
+        /* An unset duration string falls back to its hour field */
+        config.server_icons_delay_max = None;
+        assert_eq!(
+            config.icon_delay_range().unwrap(),
+            (Duration::from_mins(30), Duration::from_hours(6))
+        );
+
+        config.server_icons_delay_max = Some(String::from("2h"));
+        let (min, max) = config.icon_delay_range().unwrap();
+        assert_eq!(max, Duration::from_hours(2));
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let delay = icon_delay(min, max, &mut rng).unwrap().unwrap();
+            assert!((min..=max).contains(&delay));
+        }
+
+        config.server_icons_delay_min = Some(String::from("soon"));
+        let error = config.icon_delay_range().unwrap_err().to_string();
+        assert!(error.contains("server_icons_delay_min 'soon'"));
+    }
+
+    #[test]
+    fn icon_delay_edges() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let hour = Duration::from_hours(1);
+
+        assert_eq!(icon_delay(hour, Duration::ZERO, &mut rng).unwrap(), None);
+        assert_eq!(icon_delay(hour, hour, &mut rng).unwrap(), Some(hour));
+        assert_eq!(
+            icon_delay(Duration::ZERO, Duration::ZERO, &mut rng).unwrap(),
+            None
+        );
+        assert!(icon_delay(Duration::from_hours(2), hour, &mut rng).is_err());
+    }
+
+    #[test]
+    fn icon_expiry_reads_the_filename_and_sidecar() {
+        let date = |text| NaiveDate::parse_from_str(text, "%Y-%m-%d").ok();
+        let meta = IconMeta::default();
+
+        assert_eq!(
+            icon_expiry(Path::new("xmas__until_2026-12-26.png"), &meta),
+            date("2026-12-26")
+        );
+        assert_eq!(icon_expiry(Path::new("plain.png"), &meta), None);
+        assert_eq!(icon_expiry(Path::new("bad__until_soon.png"), &meta), None);
+        assert_eq!(
+            icon_expiry(Path::new("bad__until_2026-13-01.png"), &meta),
+            None
+        );
+
+        /* The sidecar wins over the filename */
+        let meta = IconMeta {
+            until: Some(String::from(" 2027-01-02 ")),
+            ..IconMeta::default()
+        };
+        assert_eq!(
+            icon_expiry(Path::new("xmas__until_2026-12-26.png"), &meta),
+            date("2027-01-02")
+        );
+    }
+
+    #[test]
+    fn select_icon_skips_expired_icons() {
+        let temp = TempDir::new();
+        fs::write(temp.path().join("plain.png.meta"), "until = \"2026-03-01\"").unwrap();
+        let candidates = icon_candidates(
+            &temp,
+            &[
+                "old__until_2026-01-01.png",
+                "last__until_2026-06-01.png",
+                "plain.png",
+            ],
+        );
+
+        /* Expiry dates are inclusive, the icon is still picked on its last day */
+        let june = Utc.with_ymd_and_hms(2026, 6, 1, 12, 0, 0).unwrap();
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let selected = select_icon(
+                &candidates,
+                &Config::default(),
+                &IconWeights::default(),
+                SystemTime::from(june),
+                &mut rng,
+            )
+            .unwrap();
+            assert_eq!(
+                icon_filename(&selected.path).unwrap(),
+                "last__until_2026-06-01.png"
+            );
+        }
+
+        let later = Utc.with_ymd_and_hms(2026, 6, 2, 0, 0, 0).unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(select_icon(
+            &candidates,
+            &Config::default(),
+            &IconWeights::default(),
+            SystemTime::from(later),
+            &mut rng,
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn recycle_without_batch_moves_everything() {
+        let temp = TempDir::new();
+        let unused = temp.subdir("unused");
+        let used = temp.subdir("used");
+        touch(&used, "a.png", None);
+        touch(&used, "b.png", None);
+
+        let moved = recycle_used_icons(&unused, &used, None, false, &BTreeMap::new()).unwrap();
+        assert_eq!(names(&moved), ["a.png", "b.png"]);
+        assert!(load_icon_paths(&used, false).unwrap().is_empty());
+    }
 }